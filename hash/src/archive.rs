@@ -0,0 +1,202 @@
+use crate::decompress::{self, Decompress};
+use crate::manifest::{self, normalize_path};
+use crate::{bytes_to_hex_string, get_algorithm, HashAlgorithm, HashImpl};
+use clap::{Arg, ArgMatches, Command};
+use serde_json::json;
+use std::fs::File;
+use std::io::Read;
+
+pub fn command() -> Command<'static> {
+    Command::new("archive")
+        .about("Hash the members of a tar (optionally gzip, xz, zstd or bz2 compressed) or zip archive without extracting it, printing a manifest")
+        .arg(
+            Arg::new("archive")
+                .value_name("archive")
+                .help("Archive to read")
+                .required(true)
+        )
+        .arg(
+            Arg::new("member")
+                .short('m')
+                .long("member")
+                .value_name("path")
+                .help("Only hash this member. Can be provided multiple times")
+                .takes_value(true)
+                .multiple_occurrences(true)
+        )
+        .arg(
+            Arg::new("long")
+                .short('l')
+                .long("long")
+                .help("Also print the size of each member, between the digest and the path")
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print the members as JSON")
+        )
+}
+
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub path: String,
+    pub size: u64,
+    pub digest: Vec<u8>,
+}
+
+fn is_zip(path: &str) -> std::io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let n = File::open(path)?.read(&mut magic)?;
+    Ok(n == 4 && (magic == *b"PK\x03\x04" || magic == *b"PK\x05\x06"))
+}
+
+/// Hash the regular file members of an archive accepted by `wanted`, in archive order.
+pub fn hash_members<F>(path: &str, algo: HashAlgorithm, wanted: F) -> std::io::Result<Vec<Member>>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut members = Vec::new();
+    if is_zip(path)? {
+        let mut zip = zip::ZipArchive::new(File::open(path)?)?;
+        for index in 0..zip.len() {
+            let mut file = zip.by_index(index)?;
+            let name = normalize_path(file.name()).to_string();
+            if !file.is_file() || !wanted(&name) {
+                continue;
+            }
+            let size = file.size();
+            let digest = HashImpl::digest_reader(&mut file, algo)?;
+            members.push(Member { path: name, size, digest });
+        }
+    } else {
+        let reader = decompress::decoder(File::open(path)?, Decompress::Auto)?;
+        let mut tar = tar::Archive::new(reader);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = normalize_path(&entry.path()?.to_string_lossy()).to_string();
+            if !wanted(&name) {
+                continue;
+            }
+            let size = entry.size();
+            let digest = HashImpl::digest_reader(&mut entry, algo)?;
+            members.push(Member { path: name, size, digest });
+        }
+    }
+    Ok(members)
+}
+
+pub fn run(matches: &ArgMatches) {
+    let algo = get_algorithm(matches);
+    let path = matches.value_of("archive").unwrap();
+    let selected: Option<Vec<&str>> = matches
+        .values_of("member")
+        .map(|v| v.map(normalize_path).collect());
+
+    let mut members = match hash_members(path, algo, |name| {
+        selected.as_ref().is_none_or(|s| s.contains(&name))
+    }) {
+        Ok(m) => m,
+        Err(err) => {
+            eprintln!("Cannot read archive {}: {}", path, err);
+            std::process::exit(exitcode::DATAERR);
+        }
+    };
+    if let Some(selected) = &selected {
+        if let Some(name) = selected.iter().find(|n| !members.iter().any(|m| m.path == **n)) {
+            eprintln!("No such member in {}: {}", path, name);
+            std::process::exit(exitcode::DATAERR);
+        }
+    }
+    // Sorted like a manifest of the extracted tree, so the two can be compared directly.
+    members.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if matches.is_present("json") {
+        let json_members: Vec<_> = members
+            .iter()
+            .map(|m| json!({ "path": m.path, "size": m.size, "hash": bytes_to_hex_string(&m.digest) }))
+            .collect();
+        let doc = json!({
+            "archive": path,
+            "algorithm": format!("{:?}", algo),
+            "members": json_members,
+        });
+        println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+        return;
+    }
+
+    for m in members.iter() {
+        let digest = bytes_to_hex_string(&m.digest);
+        if matches.is_present("quiet") {
+            println!("{}", digest);
+        } else if matches.is_present("long") {
+            let (escaped, name) = manifest::escape_path(&m.path);
+            println!("{}{}  {}  {}", if escaped { "\\" } else { "" }, digest, m.size, name);
+        } else {
+            println!("{}", manifest::format_line(&digest, &m.path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn tar_bytes() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut add = |name: &str, kind: tar::EntryType, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, data).unwrap();
+        };
+        add("./dir/", tar::EntryType::Directory, b"");
+        add("./dir/b.txt", tar::EntryType::Regular, b"world");
+        add("a.txt", tar::EntryType::Regular, b"hello");
+        builder.into_inner().unwrap()
+    }
+
+    fn zip_bytes() -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default();
+        zip.add_directory("dir/", options).unwrap();
+        zip.start_file("dir/b.txt", options).unwrap();
+        zip.write_all(b"world").unwrap();
+        zip.start_file("a.txt", options).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn members(content: &[u8], wanted: fn(&str) -> bool) -> Vec<(String, u64, String)> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), content).unwrap();
+        let members = hash_members(&file.path().to_string_lossy(), HashAlgorithm::SHA256, wanted).unwrap();
+        members.into_iter().map(|m| (m.path, m.size, bytes_to_hex_string(&m.digest))).collect()
+    }
+
+    #[test]
+    fn hashes_file_members_in_archive_order() {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&tar_bytes()).unwrap();
+        let tar_gz = gzip.finish().unwrap();
+
+        for (name, content) in [("tar", tar_bytes()), ("tar.gz", tar_gz), ("zip", zip_bytes())] {
+            let found = members(&content, |_| true);
+            assert_eq!(found.len(), 2, "{}", name);
+            assert_eq!((found[0].0.as_str(), found[0].1), ("dir/b.txt", 5), "{}", name);
+            assert_eq!(found[1], ("a.txt".to_string(), 5, HELLO_SHA256.to_string()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn selects_members() {
+        let found = members(&zip_bytes(), |name| name == "a.txt");
+        assert_eq!(found, [("a.txt".to_string(), 5, HELLO_SHA256.to_string())]);
+    }
+}
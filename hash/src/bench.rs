@@ -0,0 +1,159 @@
+use crate::{HashAlgorithm, HashImpl};
+use clap::{Arg, ArgMatches, Command};
+use serde_json::json;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SIZES: [(usize, &str); 4] = [
+    (64, "64 B"),
+    (4 * 1024, "4 KiB"),
+    (1024 * 1024, "1 MiB"),
+    (64 * 1024 * 1024, "64 MiB"),
+];
+
+const WARM_UP_TIME: Duration = Duration::from_millis(50);
+const REPETITION_TIME: Duration = Duration::from_millis(100);
+
+pub fn command() -> Command<'static> {
+    Command::new("bench")
+        .about("Measure the throughput of every hash algorithm on this machine")
+        .arg(
+            Arg::new("repeat")
+                .short('r')
+                .long("repeat")
+                .value_name("n")
+                .help("Number of timed repetitions per algorithm and buffer size, the median is reported")
+                .takes_value(true)
+                .default_value("5")
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print the results as JSON")
+        )
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    pub algo: HashAlgorithm,
+    pub size: usize,
+    pub iterations: u64,
+    pub bytes_per_sec: f64,
+}
+
+/// Deterministic filler so every run hashes the same bytes.
+fn bench_buffer(size: usize) -> Vec<u8> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn run_iterations(buf: &[u8], algo: HashAlgorithm, iterations: u64) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(HashImpl::digest(black_box(buf), algo));
+    }
+    start.elapsed()
+}
+
+pub fn bench_one(buf: &[u8], algo: HashAlgorithm, repeat: usize) -> BenchResult {
+    // Warm up caches and clocks, and learn how many iterations fill one repetition.
+    let mut warm_up_iterations = 0u64;
+    let start = Instant::now();
+    while warm_up_iterations == 0 || start.elapsed() < WARM_UP_TIME {
+        run_iterations(buf, algo, 1);
+        warm_up_iterations += 1;
+    }
+    let per_iteration = start.elapsed().as_secs_f64() / warm_up_iterations as f64;
+    let iterations = ((REPETITION_TIME.as_secs_f64() / per_iteration).ceil() as u64).max(1);
+
+    let mut rates: Vec<f64> = (0..repeat.max(1))
+        .map(|_| {
+            let elapsed = run_iterations(buf, algo, iterations).as_secs_f64();
+            (buf.len() as u64 * iterations) as f64 / elapsed
+        })
+        .collect();
+    rates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    BenchResult {
+        algo,
+        size: buf.len(),
+        iterations,
+        bytes_per_sec: rates[rates.len() / 2],
+    }
+}
+
+pub fn run(matches: &ArgMatches) {
+    let repeat: usize = matches.value_of_t("repeat").unwrap_or_else(|e| e.exit());
+    let selected: Vec<HashAlgorithm> = HashAlgorithm::ALL
+        .iter()
+        .copied()
+        .filter(|algo| matches.is_present(algo.flag_name()))
+        .collect();
+    let algos = if selected.is_empty() { HashAlgorithm::ALL.to_vec() } else { selected };
+
+    let mut results = Vec::new();
+    for (size, _) in SIZES.iter() {
+        let buf = bench_buffer(*size);
+        for algo in algos.iter() {
+            results.push(bench_one(&buf, *algo, repeat));
+        }
+    }
+
+    if matches.is_present("json") {
+        let json_results: Vec<_> = results
+            .iter()
+            .map(|r| {
+                json!({
+                    "algorithm": format!("{:?}", r.algo),
+                    "size": r.size,
+                    "iterations": r.iterations,
+                    "mb_per_sec": r.bytes_per_sec / 1_000_000.0,
+                })
+            })
+            .collect();
+        let doc = json!({ "repeat": repeat, "results": json_results });
+        println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+        return;
+    }
+
+    print!("{:<10}", "ALGORITHM");
+    for (_, label) in SIZES.iter() {
+        print!("{:>16}", label);
+    }
+    println!();
+    for algo in algos.iter() {
+        print!("{:<10}", format!("{:?}", algo));
+        for r in results.iter().filter(|r| r.algo == *algo) {
+            print!("{:>16}", format!("{:.1} MB/s", r.bytes_per_sec / 1_000_000.0));
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_is_deterministic() {
+        let buf = bench_buffer(4096);
+        assert_eq!(buf, bench_buffer(4096));
+        assert_eq!(&bench_buffer(64)[..], &buf[..64]);
+        assert!(buf.iter().any(|b| *b != buf[0]));
+    }
+
+    #[test]
+    fn measures_throughput() {
+        let result = bench_one(&bench_buffer(64), HashAlgorithm::BLAKE3, 1);
+        assert_eq!((result.algo, result.size), (HashAlgorithm::BLAKE3, 64));
+        assert!(result.iterations >= 1);
+        assert!(result.bytes_per_sec.is_finite() && result.bytes_per_sec > 0.0);
+    }
+}
@@ -0,0 +1,348 @@
+//! Per-block digests of a file.
+//!
+//! A block list is printed as text so it can be saved and diffed later:
+//!
+//! ```text
+//! # hash-blocks v1 algo=SHA256 block-size=4096 size=10000 path=rootfs.img
+//! 0 4096 <hex digest>
+//! 4096 4096 <hex digest>
+//! 8192 1808 <hex digest>
+//! root <hex digest>
+//! ```
+//!
+//! Each line holds a block's offset, length and digest. The optional root is the
+//! digest of all block digests concatenated in order.
+
+use crate::encoding::constant_time_eq;
+use crate::{bytes_to_hex_string, get_algorithm, parse_size, HashAlgorithm, HashImpl};
+use clap::{Arg, ArgMatches, Command};
+use serde_json::json;
+use std::fs::File;
+use std::io::Read;
+
+const HEADER: &str = "# hash-blocks v1";
+
+#[derive(Clone, Debug)]
+pub struct BlockList {
+    pub path: String,
+    pub algo: HashAlgorithm,
+    pub block_size: u64,
+    pub size: u64,
+    pub blocks: Vec<Vec<u8>>,
+}
+
+impl BlockList {
+    pub fn from_file(path: &str, algo: HashAlgorithm, block_size: u64) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut blocks = Vec::new();
+        let mut size = 0;
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            let n = (&mut file).take(block_size).read_to_end(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            size += n as u64;
+            blocks.push(HashImpl::digest(&buf, algo));
+        }
+        Ok(BlockList {
+            path: path.to_string(),
+            algo,
+            block_size,
+            size,
+            blocks,
+        })
+    }
+
+    pub fn root(&self) -> Vec<u8> {
+        HashImpl::digest(&self.blocks.concat(), self.algo)
+    }
+
+    /// Offset and length of block `index`.
+    pub fn range(&self, index: usize) -> (u64, u64) {
+        let offset = index as u64 * self.block_size;
+        (offset, self.block_size.min(self.size.saturating_sub(offset)))
+    }
+
+    pub fn to_text(&self, with_root: bool) -> String {
+        let mut text = format!(
+            "{} algo={:?} block-size={} size={} path={}\n",
+            HEADER, self.algo, self.block_size, self.size, self.path
+        );
+        for (index, digest) in self.blocks.iter().enumerate() {
+            let (offset, len) = self.range(index);
+            text += &format!("{} {} {}\n", offset, len, bytes_to_hex_string(digest));
+        }
+        if with_root {
+            text += &format!("root {}\n", bytes_to_hex_string(&self.root()));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines();
+        let header = lines
+            .next()
+            .and_then(|l| l.strip_prefix(HEADER))
+            .ok_or("missing block list header")?;
+        let (mut algo, mut block_size, mut size) = (None, 0, None);
+        // `path` is last so that it may contain spaces.
+        let (fields, path) = header.split_once(" path=").unwrap_or((header, ""));
+        for field in fields.split_whitespace() {
+            let (key, value) = field.split_once('=').ok_or(format!("invalid header field '{}'", field))?;
+            match key {
+                "algo" => algo = Some(value.parse::<HashAlgorithm>()?),
+                "block-size" => block_size = value.parse().map_err(|_| format!("invalid block size '{}'", value))?,
+                "size" => size = Some(value.parse().map_err(|_| format!("invalid size '{}'", value))?),
+                _ => {}
+            }
+        }
+        let algo = algo.ok_or("missing algorithm")?;
+        if block_size == 0 {
+            return Err("missing block size".to_string());
+        }
+        let size: u64 = size.ok_or("missing size")?;
+        let mut blocks = Vec::new();
+        for line in lines {
+            let parts: Vec<_> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [] | ["root", _] => {}
+                [_, _, digest] => {
+                    let bytes = crate::encoding::decode_digest(digest)
+                        .filter(|b| b.len() == algo.output_len())
+                        .ok_or(format!("invalid {:?} digest '{}'", algo, digest))?;
+                    blocks.push(bytes);
+                }
+                _ => return Err(format!("invalid block line '{}'", line)),
+            }
+        }
+        // Every block but the last is full, and the last one holds at least a byte.
+        if size.div_ceil(block_size) != blocks.len() as u64 {
+            return Err(format!("{} blocks of {} bytes cannot hold {} bytes", blocks.len(), block_size, size));
+        }
+        Ok(BlockList { path: path.to_string(), algo, block_size, size, blocks })
+    }
+}
+
+pub fn print_blocks(file: &str, algo: HashAlgorithm, block_size: u64, with_root: bool, print_hash_only: bool) {
+    let list = match BlockList::from_file(file, algo, block_size) {
+        Ok(l) => l,
+        Err(err) => {
+            eprintln!("Cannot read file {}: {}", file, err);
+            std::process::exit(exitcode::IOERR);
+        }
+    };
+    if print_hash_only {
+        for digest in list.blocks.iter() {
+            println!("{}", bytes_to_hex_string(digest));
+        }
+        if with_root {
+            println!("{}", bytes_to_hex_string(&list.root()));
+        }
+    } else {
+        println!("{}", list.to_text(with_root));
+    }
+}
+
+pub fn command() -> Command<'static> {
+    Command::new("block-diff")
+        .about("Locate the regions where two files differ by comparing per-block digests. Exits with 0 when all blocks match and 1 otherwise")
+        .arg(
+            Arg::new("a")
+                .value_name("A")
+                .help("First file, or a block list saved from --block-size")
+                .required(true)
+        )
+        .arg(
+            Arg::new("b")
+                .value_name("B")
+                .help("Second file, or a block list saved from --block-size")
+                .required(true)
+        )
+        .arg(
+            Arg::new("block-size")
+                .short('b')
+                .long("block-size")
+                .value_name("size")
+                .help("Block size used to hash files given directly, e.g. 4096, 64K or 1M. Defaults to the block size of a saved list on the other side, or 4096")
+                .takes_value(true)
+                .validator(|s| parse_size(s).ok_or("should be a positive size such as 4096, 64K or 1M"))
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print the differing regions as JSON")
+        )
+}
+
+const DEFAULT_BLOCK_SIZE: u64 = 4096;
+
+/// The block list saved at `path`, or `None` when it is a file to hash.
+fn load_saved(path: &str) -> Option<BlockList> {
+    let mut magic = [0u8; HEADER.len()];
+    let is_list = File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| magic == HEADER.as_bytes())
+        .unwrap_or(false);
+    if !is_list {
+        return None;
+    }
+    match std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|s| BlockList::parse(&s)) {
+        Ok(list) => Some(list),
+        Err(err) => {
+            eprintln!("Cannot read block list {}: {}", path, err);
+            std::process::exit(exitcode::DATAERR);
+        }
+    }
+}
+
+fn load(path: &str, algo: HashAlgorithm, block_size: u64) -> BlockList {
+    BlockList::from_file(path, algo, block_size).unwrap_or_else(|err| {
+        eprintln!("Cannot read file {}: {}", path, err);
+        std::process::exit(exitcode::IOERR);
+    })
+}
+
+fn block_differs(a: &BlockList, b: &BlockList, index: usize) -> bool {
+    match (a.blocks.get(index), b.blocks.get(index)) {
+        (Some(x), Some(y)) => !constant_time_eq(x, y),
+        _ => true,
+    }
+}
+
+/// Contiguous runs of differing blocks as `(offset, length)` pairs.
+pub fn diff_regions(a: &BlockList, b: &BlockList) -> Vec<(u64, u64)> {
+    let count = a.blocks.len().max(b.blocks.len());
+    let mut regions: Vec<(u64, u64)> = Vec::new();
+    for index in (0..count).filter(|i| block_differs(a, b, *i)) {
+        let (offset, len_a) = a.range(index);
+        let (_, len_b) = b.range(index);
+        let len = len_a.max(len_b);
+        if len == 0 {
+            continue;
+        }
+        match regions.last_mut() {
+            Some((start, run)) if *start + *run == offset => *run += len,
+            _ => regions.push((offset, len)),
+        }
+    }
+    regions
+}
+
+/// Returns whether every block matched.
+pub fn run(matches: &ArgMatches) -> bool {
+    let (path_a, path_b) = (matches.value_of("a").unwrap(), matches.value_of("b").unwrap());
+    let (saved_a, saved_b) = (load_saved(path_a), load_saved(path_b));
+    // A file compared with a saved list is cut into blocks of the list's size and hashed
+    // with the list's algorithm.
+    let saved = saved_a.as_ref().or(saved_b.as_ref());
+    let block_size = match matches.value_of("block-size").and_then(parse_size) {
+        Some(size) => size,
+        None => saved.map_or(DEFAULT_BLOCK_SIZE, |l| l.block_size),
+    };
+    let algo = match saved {
+        Some(list) if !(matches.is_present("md5") || matches.is_present("sha256") || matches.is_present("blake3")) => list.algo,
+        _ => get_algorithm(matches),
+    };
+    let a = saved_a.unwrap_or_else(|| load(path_a, algo, block_size));
+    let b = saved_b.unwrap_or_else(|| load(path_b, algo, block_size));
+    if a.algo != b.algo || a.block_size != b.block_size {
+        eprintln!(
+            "Cannot compare block lists made with {:?}/{} and {:?}/{}",
+            a.algo, a.block_size, b.algo, b.block_size
+        );
+        std::process::exit(exitcode::DATAERR);
+    }
+
+    let regions = diff_regions(&a, &b);
+    let total = a.blocks.len().max(b.blocks.len());
+    let differing = (0..total).filter(|i| block_differs(&a, &b, *i)).count();
+
+    if matches.is_present("json") {
+        let json_regions: Vec<_> = regions
+            .iter()
+            .map(|(offset, len)| json!({ "offset": offset, "length": len }))
+            .collect();
+        let doc = json!({
+            "algorithm": format!("{:?}", a.algo),
+            "block_size": a.block_size,
+            "blocks": total,
+            "differing_blocks": differing,
+            "regions": json_regions,
+        });
+        println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+    } else if !matches.is_present("quiet") {
+        for (offset, len) in regions.iter() {
+            println!("[DIFF] [0x{:08x}-0x{:08x}] [{} BYTES]", offset, offset + len - 1, len);
+        }
+        println!("{} of {} blocks differ", differing, total);
+    }
+    regions.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(content: &[u8], block_size: u64) -> BlockList {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), content).unwrap();
+        BlockList::from_file(&file.path().to_string_lossy(), HashAlgorithm::SHA256, block_size).unwrap()
+    }
+
+    #[test]
+    fn splits_into_blocks() {
+        let blocks = list(b"abcdefg", 3);
+        assert_eq!(blocks.size, 7);
+        assert_eq!(blocks.blocks.len(), 3);
+        assert_eq!(blocks.blocks[0], HashImpl::digest(b"abc", HashAlgorithm::SHA256));
+        assert_eq!(blocks.blocks[2], HashImpl::digest(b"g", HashAlgorithm::SHA256));
+        assert_eq!(blocks.range(2), (6, 1));
+        assert_eq!(blocks.root(), HashImpl::digest(&blocks.blocks.concat(), HashAlgorithm::SHA256));
+        assert!(list(b"", 3).blocks.is_empty());
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let blocks = list(b"abcdefg", 3);
+        let text = blocks.to_text(true);
+        assert!(text.starts_with("# hash-blocks v1 algo=SHA256 block-size=3 size=7 path="));
+        assert!(text.contains(&format!("\n6 1 {}\nroot ", bytes_to_hex_string(&blocks.blocks[2]))));
+        let parsed = BlockList::parse(&text).unwrap();
+        assert_eq!((parsed.algo, parsed.block_size, parsed.size), (HashAlgorithm::SHA256, 3, 7));
+        assert_eq!(parsed.path, blocks.path);
+        assert_eq!(parsed.blocks, blocks.blocks);
+    }
+
+    #[test]
+    fn rejects_invalid_lists() {
+        assert!(BlockList::parse("0 3 abcd\n").is_err());
+        assert!(BlockList::parse("# hash-blocks v1 algo=SHA256 size=7\n").is_err());
+        assert!(BlockList::parse("# hash-blocks v1 algo=SHA256 block-size=3\n0 3\n").is_err());
+        let text = list(b"abcdefg", 3).to_text(false);
+        assert_eq!(BlockList::parse(&text.replace(" size=7", "")).unwrap_err(), "missing size");
+        assert_eq!(BlockList::parse(&text.replace(" size=7", " size=10")).unwrap_err(), "3 blocks of 3 bytes cannot hold 10 bytes");
+        assert_eq!(BlockList::parse(&text.replace(" size=7", " size=6")).unwrap_err(), "3 blocks of 3 bytes cannot hold 6 bytes");
+        assert!(BlockList::parse(&text.replace("algo=SHA256", "algo=MD5")).is_err());
+    }
+
+    #[test]
+    fn files_are_hashed_like_the_saved_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let (file, saved) = (dir.path().join("file"), dir.path().join("file.blocks"));
+        std::fs::write(&file, b"abcdefg").unwrap();
+        let list = BlockList::from_file(&file.to_string_lossy(), HashAlgorithm::BLAKE3, 3).unwrap();
+        std::fs::write(&saved, list.to_text(true)).unwrap();
+        let matches = crate::build_app().get_matches_from(["hash", "-q", "block-diff", &*saved.to_string_lossy(), &*file.to_string_lossy()]);
+        assert!(run(matches.subcommand_matches("block-diff").unwrap()));
+    }
+
+    #[test]
+    fn merges_adjacent_differing_blocks() {
+        let a = list(b"aaabbbcccdddeee", 3);
+        let b = list(b"aaaXbbcXcdddeeeff", 3);
+        assert_eq!(diff_regions(&a, &b), [(3, 6), (15, 2)]);
+        assert!(diff_regions(&a, &a).is_empty());
+    }
+}
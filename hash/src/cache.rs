@@ -0,0 +1,337 @@
+//! Persistent digest cache for `--cache`.
+//!
+//! The cache is a text file, one entry per line after a `# hash-cache v1` header:
+//!
+//! `algo TAB dev TAB inode TAB size TAB mtime_ns TAB ctime_ns TAB hex TAB path`
+//!
+//! Entries are keyed by algorithm and absolute path, so one cache serves every
+//! algorithm. A cached digest is only used while the file's device, inode, size, mtime
+//! and ctime are all unchanged. Files modified shortly before or during a run are not
+//! stored, because a second write within the same timestamp tick would go unnoticed.
+//! Digests are only cached for the raw file content, never after `--hex`,
+//! `--decompress` or `--normalize`.
+
+use crate::manifest::escape_path;
+use crate::{bytes_to_hex_string, encoding, HashAlgorithm, HashImpl};
+use clap::ArgMatches;
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "# hash-cache v1";
+
+/// Files whose mtime is this close to the start of the run are not cached.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Stamp {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime_ns: i128,
+    ctime_ns: i128,
+}
+
+impl Stamp {
+    fn of(metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            Stamp {
+                dev: metadata.dev(),
+                ino: metadata.ino(),
+                size: metadata.len(),
+                mtime_ns: metadata.mtime() as i128 * 1_000_000_000 + metadata.mtime_nsec() as i128,
+                ctime_ns: metadata.ctime() as i128 * 1_000_000_000 + metadata.ctime_nsec() as i128,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            Stamp {
+                dev: 0,
+                ino: 0,
+                size: metadata.len(),
+                mtime_ns: metadata.modified().map_or(0, |t| nanos_since_epoch(t)),
+                ctime_ns: 0,
+            }
+        }
+    }
+}
+
+fn nanos_since_epoch(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_nanos() as i128,
+        Err(e) => -(e.duration().as_nanos() as i128),
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    stamp: Stamp,
+    digest: Vec<u8>,
+    used: bool,
+}
+
+pub fn unescape_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn parse_entry(line: &str) -> Option<((HashAlgorithm, PathBuf), Entry)> {
+    let fields: Vec<&str> = line.splitn(8, '\t').collect();
+    if fields.len() != 8 {
+        return None;
+    }
+    let algo: HashAlgorithm = fields[0].parse().ok()?;
+    let stamp = Stamp {
+        dev: fields[1].parse().ok()?,
+        ino: fields[2].parse().ok()?,
+        size: fields[3].parse().ok()?,
+        mtime_ns: fields[4].parse().ok()?,
+        ctime_ns: fields[5].parse().ok()?,
+    };
+    let digest = encoding::decode_plain_hex(fields[6])?;
+    let path = PathBuf::from(unescape_path(fields[7]));
+    Some(((algo, path), Entry { stamp, digest, used: false }))
+}
+
+#[derive(Debug, Default)]
+pub struct HashCache {
+    /// Location of the cache file, `None` when caching is off.
+    path: Option<PathBuf>,
+    /// Re-hash every file and check the cached digests instead of trusting them.
+    verify: bool,
+    entries: HashMap<(HashAlgorithm, PathBuf), Entry>,
+    dirty: bool,
+    started: i128,
+}
+
+impl HashCache {
+    pub fn disabled() -> Self {
+        HashCache::default()
+    }
+
+    /// Load the cache file, starting empty when it does not exist yet. Unreadable
+    /// lines are dropped, which only costs re-hashing those files.
+    pub fn open(path: &Path, verify: bool) -> std::io::Result<Self> {
+        let mut cache = HashCache {
+            path: Some(path.to_path_buf()),
+            verify,
+            started: nanos_since_epoch(SystemTime::now()),
+            ..HashCache::default()
+        };
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(cache),
+            Err(err) => return Err(err),
+        };
+        let mut lines = BufReader::new(file).lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            eprintln!("Ignoring cache {}, it is not a hash cache file", path.display());
+            return Ok(cache);
+        }
+        for line in lines {
+            if let Some((key, entry)) = parse_entry(&line?) {
+                cache.entries.insert(key, entry);
+            }
+        }
+        Ok(cache)
+    }
+
+    /// The cache selected by `--cache` and `--verify-cache`, exiting when it cannot be read.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.value_of("cache") {
+            None => HashCache::disabled(),
+            Some(path) => match HashCache::open(Path::new(path), matches.is_present("verify-cache")) {
+                Ok(cache) => cache,
+                Err(err) => {
+                    eprintln!("Cannot read cache {}: {}", path, err);
+                    std::process::exit(exitcode::IOERR);
+                }
+            },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Non-UTF-8 paths are not cached, so that a lossy conversion never makes two
+    /// files share an entry.
+    fn key(&self, path: &Path, algo: HashAlgorithm) -> Option<(HashAlgorithm, PathBuf)> {
+        if !self.is_enabled() {
+            return None;
+        }
+        path.to_str()?;
+        Some((algo, std::path::absolute(path).ok()?))
+    }
+
+    /// The cached digest of `path`, if its metadata still matches.
+    pub fn get(&mut self, path: &Path, metadata: &Metadata, algo: HashAlgorithm) -> Option<Vec<u8>> {
+        if self.verify {
+            return None;
+        }
+        let key = self.key(path, algo)?;
+        let entry = self.entries.get_mut(&key)?;
+        if entry.stamp != Stamp::of(metadata) {
+            return None;
+        }
+        entry.used = true;
+        Some(entry.digest.clone())
+    }
+
+    /// Record the digest of `path`, given the metadata read before hashing it. With
+    /// `--verify-cache`, warn about entries whose metadata matched but whose digest
+    /// did not.
+    pub fn insert(&mut self, path: &Path, metadata: &Metadata, algo: HashAlgorithm, digest: &[u8]) {
+        let Some(key) = self.key(path, algo) else {
+            return;
+        };
+        let stamp = Stamp::of(metadata);
+        if let Some(old) = self.entries.get(&key) {
+            if self.verify && old.stamp == stamp && old.digest != digest {
+                eprintln!(
+                    "Stale cache entry for {}: cached {}, actual {}",
+                    path.display(),
+                    bytes_to_hex_string(&old.digest),
+                    bytes_to_hex_string(digest)
+                );
+            }
+        }
+        if stamp.mtime_ns + RACY_WINDOW.as_nanos() as i128 >= self.started {
+            self.dirty |= self.entries.remove(&key).is_some();
+            return;
+        }
+        let entry = Entry { stamp, digest: digest.to_vec(), used: true };
+        self.entries.insert(key, entry);
+        self.dirty = true;
+    }
+
+    /// Digest of the content of `path`, from the cache when possible.
+    pub fn file_digest(&mut self, path: &Path, metadata: &Metadata, algo: HashAlgorithm) -> std::io::Result<Vec<u8>> {
+        if let Some(digest) = self.get(path, metadata, algo) {
+            return Ok(digest);
+        }
+        let digest = HashImpl::digest_reader(&mut File::open(path)?, algo)?;
+        self.insert(path, metadata, algo, &digest);
+        Ok(digest)
+    }
+
+    /// Write the cache back, replacing the file atomically. Entries not used in this
+    /// run are kept for other trees and algorithms unless their file is gone.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let before = self.entries.len();
+        self.entries.retain(|(_, p), e| e.used || p.exists());
+        if !self.dirty && self.entries.len() == before {
+            return Ok(());
+        }
+
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| (&a.0 .1, a.0 .0.flag_name()).cmp(&(&b.0 .1, b.0 .0.flag_name())));
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".tmp{}", std::process::id()));
+        let tmp = PathBuf::from(tmp);
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        writeln!(out, "{}", HEADER)?;
+        for ((algo, file), e) in entries {
+            let (_, name) = escape_path(&file.to_string_lossy());
+            let s = &e.stamp;
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                algo.flag_name(),
+                s.dev,
+                s.ino,
+                s.size,
+                s.mtime_ns,
+                s.ctime_ns,
+                bytes_to_hex_string(&e.digest),
+                name
+            )?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file last modified an hour ago, old enough to be cached.
+    fn old_file(path: &Path, content: &str) -> Metadata {
+        std::fs::write(path, content).unwrap();
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        File::options().write(true).open(path).unwrap().set_modified(hour_ago).unwrap();
+        std::fs::metadata(path).unwrap()
+    }
+
+    #[test]
+    fn parses_entries() {
+        let line = "sha256\t1\t2\t5\t-3\t4\t2cf24dba\tdir\\\\a\\nb";
+        let ((algo, path), entry) = parse_entry(line).unwrap();
+        assert_eq!((algo, path), (HashAlgorithm::SHA256, PathBuf::from("dir\\a\nb")));
+        assert_eq!(entry.stamp, Stamp { dev: 1, ino: 2, size: 5, mtime_ns: -3, ctime_ns: 4 });
+        assert_eq!(entry.digest, [0x2c, 0xf2, 0x4d, 0xba]);
+        assert!(parse_entry("sha256\t1\t2\t5\t3\t4\tzz\tpath").is_none());
+        assert!(parse_entry("crc32\t1\t2\t5\t3\t4\t00\tpath").is_none());
+        assert!(parse_entry("sha256\t1\t2\t5").is_none());
+    }
+
+    #[test]
+    fn saved_digests_are_reused_until_the_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_path = dir.path().join("cache");
+        let file = dir.path().join("a\nb.txt");
+        let metadata = old_file(&file, "hello");
+
+        let mut cache = HashCache::open(&cache_path, false).unwrap();
+        let digest = cache.file_digest(&file, &metadata, HashAlgorithm::SHA256).unwrap();
+        assert_eq!(digest, HashImpl::digest(b"hello", HashAlgorithm::SHA256));
+        cache.save().unwrap();
+
+        let mut cache = HashCache::open(&cache_path, false).unwrap();
+        assert_eq!(cache.get(&file, &metadata, HashAlgorithm::SHA256), Some(digest.clone()));
+        assert_eq!(cache.get(&file, &metadata, HashAlgorithm::MD5), None);
+        let mut verifying = HashCache::open(&cache_path, true).unwrap();
+        assert_eq!(verifying.get(&file, &metadata, HashAlgorithm::SHA256), None);
+
+        let changed = old_file(&file, "world");
+        assert_eq!(cache.get(&file, &changed, HashAlgorithm::SHA256), None);
+    }
+
+    #[test]
+    fn recently_modified_files_are_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("fresh.txt");
+        std::fs::write(&file, "hello").unwrap();
+        let metadata = std::fs::metadata(&file).unwrap();
+
+        let mut cache = HashCache::open(&dir.path().join("cache"), false).unwrap();
+        cache.file_digest(&file, &metadata, HashAlgorithm::SHA256).unwrap();
+        assert_eq!(cache.get(&file, &metadata, HashAlgorithm::SHA256), None);
+        cache.save().unwrap();
+        assert!(!dir.path().join("cache").exists());
+    }
+}
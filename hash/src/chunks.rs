@@ -0,0 +1,286 @@
+//! Content-defined chunking with FastCDC (2020 variant, normalization level 1).
+//!
+//! Unlike fixed blocks, chunk boundaries follow the content, so an insertion near the
+//! start of a file only changes the chunks around it. Comparing the chunk digests of
+//! two files estimates how much of one deduplicates against the other.
+
+use crate::{bytes_to_hex_string, get_algorithm, parse_size, HashAlgorithm, HashImpl};
+use clap::{Arg, ArgMatches, Command};
+use fastcdc::v2020::{self, StreamCDC};
+use serde_json::json;
+use std::collections::HashSet;
+
+pub fn command() -> Command<'static> {
+    Command::new("chunks")
+        .about("Split files into content-defined chunks and print the boundaries and digest of each. With several files, report how many chunks each shares with the first")
+        .arg(
+            Arg::new("file")
+                .value_name("file")
+                .help("File to chunk. Can be provided multiple times")
+                .required(true)
+                .multiple_values(true)
+        )
+        .arg(
+            Arg::new("min")
+                .long("min")
+                .value_name("size")
+                .help("Minimum chunk size, between 64 and 1M")
+                .takes_value(true)
+                .default_value("16K")
+                .validator(size_validator)
+        )
+        .arg(
+            Arg::new("avg")
+                .long("avg")
+                .value_name("size")
+                .help("Average chunk size, between 256 and 4M")
+                .takes_value(true)
+                .default_value("64K")
+                .validator(size_validator)
+        )
+        .arg(
+            Arg::new("max")
+                .long("max")
+                .value_name("size")
+                .help("Maximum chunk size, between 1K and 16M")
+                .takes_value(true)
+                .default_value("256K")
+                .validator(size_validator)
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print the chunks and the shared statistics as JSON")
+        )
+}
+
+fn size_validator(s: &str) -> Result<u64, &'static str> {
+    parse_size(s).ok_or("should be a positive size such as 4096, 64K or 1M")
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkParams {
+    pub min: u32,
+    pub avg: u32,
+    pub max: u32,
+}
+
+impl ChunkParams {
+    pub fn validate(&self) -> Result<(), String> {
+        let in_range = |v: u32, lo: u32, hi: u32| (lo..=hi).contains(&v);
+        if !in_range(self.min, v2020::MINIMUM_MIN, v2020::MINIMUM_MAX) {
+            return Err(format!("--min must be between {} and {}", v2020::MINIMUM_MIN, v2020::MINIMUM_MAX));
+        }
+        if !in_range(self.avg, v2020::AVERAGE_MIN, v2020::AVERAGE_MAX) {
+            return Err(format!("--avg must be between {} and {}", v2020::AVERAGE_MIN, v2020::AVERAGE_MAX));
+        }
+        if !in_range(self.max, v2020::MAXIMUM_MIN, v2020::MAXIMUM_MAX) {
+            return Err(format!("--max must be between {} and {}", v2020::MAXIMUM_MIN, v2020::MAXIMUM_MAX));
+        }
+        if self.min > self.avg || self.avg > self.max {
+            return Err("chunk sizes must satisfy --min <= --avg <= --max".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    pub offset: u64,
+    pub length: usize,
+    pub digest: Vec<u8>,
+}
+
+pub fn chunk_file(path: &str, params: ChunkParams, algo: HashAlgorithm) -> std::io::Result<Vec<Chunk>> {
+    let file = std::fs::File::open(path)?;
+    StreamCDC::new(file, params.min, params.avg, params.max)
+        .map(|result| {
+            let chunk = result?;
+            Ok(Chunk {
+                offset: chunk.offset,
+                length: chunk.length,
+                digest: HashImpl::digest(&chunk.data, algo),
+            })
+        })
+        .collect()
+}
+
+struct Shared {
+    chunks: usize,
+    bytes: u64,
+}
+
+fn shared_with(reference: &HashSet<&[u8]>, chunks: &[Chunk]) -> Shared {
+    let shared: Vec<_> = chunks
+        .iter()
+        .filter(|c| reference.contains(c.digest.as_slice()))
+        .collect();
+    Shared {
+        chunks: shared.len(),
+        bytes: shared.iter().map(|c| c.length as u64).sum(),
+    }
+}
+
+pub fn run(matches: &ArgMatches) {
+    let algo = get_algorithm(matches);
+    let size_of = |name| parse_size(matches.value_of(name).unwrap()).unwrap().min(u32::MAX as u64) as u32;
+    let params = ChunkParams {
+        min: size_of("min"),
+        avg: size_of("avg"),
+        max: size_of("max"),
+    };
+    if let Err(err) = params.validate() {
+        eprintln!("{}", err);
+        std::process::exit(exitcode::USAGE);
+    }
+
+    let files: Vec<&str> = matches.values_of("file").unwrap().collect();
+    let chunked: Vec<Vec<Chunk>> = files
+        .iter()
+        .map(|file| match chunk_file(file, params, algo) {
+            Ok(chunks) => chunks,
+            Err(err) => {
+                eprintln!("Cannot read file {}: {}", file, err);
+                std::process::exit(exitcode::IOERR);
+            }
+        })
+        .collect();
+    let reference: HashSet<&[u8]> = chunked[0].iter().map(|c| c.digest.as_slice()).collect();
+    let shared: Vec<Shared> = chunked[1..].iter().map(|chunks| shared_with(&reference, chunks)).collect();
+    let total_bytes = |chunks: &[Chunk]| -> u64 { chunks.iter().map(|c| c.length as u64).sum() };
+
+    if matches.is_present("json") {
+        let json_files: Vec<_> = files
+            .iter()
+            .zip(chunked.iter())
+            .map(|(file, chunks)| {
+                let json_chunks: Vec<_> = chunks
+                    .iter()
+                    .map(|c| json!({ "offset": c.offset, "length": c.length, "hash": bytes_to_hex_string(&c.digest) }))
+                    .collect();
+                json!({ "path": file, "size": total_bytes(chunks), "chunks": json_chunks })
+            })
+            .collect();
+        let json_shared: Vec<_> = files[1..]
+            .iter()
+            .zip(chunked[1..].iter())
+            .zip(shared.iter())
+            .map(|((file, chunks), s)| {
+                json!({
+                    "path": file,
+                    "reference": files[0],
+                    "shared_chunks": s.chunks,
+                    "total_chunks": chunks.len(),
+                    "shared_bytes": s.bytes,
+                    "total_bytes": total_bytes(chunks),
+                })
+            })
+            .collect();
+        let doc = json!({
+            "algorithm": format!("{:?}", algo),
+            "min": params.min,
+            "avg": params.avg,
+            "max": params.max,
+            "files": json_files,
+            "shared": json_shared,
+        });
+        println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+        return;
+    }
+
+    let print_hash_only = matches.is_present("quiet");
+    for (file, chunks) in files.iter().zip(chunked.iter()) {
+        if !print_hash_only {
+            println!(
+                "# chunks algo={:?} min={} avg={} max={} path={}",
+                algo, params.min, params.avg, params.max, file
+            );
+        }
+        for c in chunks.iter() {
+            if print_hash_only {
+                println!("{}", bytes_to_hex_string(&c.digest));
+            } else {
+                println!("{} {} {}", c.offset, c.length, bytes_to_hex_string(&c.digest));
+            }
+        }
+        println!();
+    }
+    for ((file, chunks), s) in files[1..].iter().zip(chunked[1..].iter()).zip(shared.iter()) {
+        let total = total_bytes(chunks);
+        let percent = if total > 0 { s.bytes as f64 * 100.0 / total as f64 } else { 100.0 };
+        println!(
+            "[SHARED] [{}] [{} of {} CHUNKS] [{} of {} BYTES] [{:.1}%]",
+            file,
+            s.chunks,
+            chunks.len(),
+            s.bytes,
+            total,
+            percent
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: ChunkParams = ChunkParams { min: 256, avg: 1024, max: 4096 };
+
+    /// Deterministic incompressible bytes (xorshift64).
+    fn noise(len: usize, mut state: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunk_bytes(data: &[u8]) -> Vec<Chunk> {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), data).unwrap();
+        chunk_file(&file.path().to_string_lossy(), SMALL, HashAlgorithm::SHA256).unwrap()
+    }
+
+    #[test]
+    fn validates_sizes() {
+        assert!(SMALL.validate().is_ok());
+        assert!(ChunkParams { min: 16 * 1024, avg: 64 * 1024, max: 256 * 1024 }.validate().is_ok());
+        assert!(ChunkParams { min: 32, ..SMALL }.validate().is_err());
+        assert!(ChunkParams { avg: 8 << 20, ..SMALL }.validate().is_err());
+        assert!(ChunkParams { min: 2048, avg: 1024, max: 4096 }.validate().is_err());
+    }
+
+    #[test]
+    fn chunks_cover_the_file() {
+        let data = noise(100_000, 1);
+        let chunks = chunk_bytes(&data);
+        let mut offset = 0;
+        for (i, c) in chunks.iter().enumerate() {
+            assert_eq!(c.offset, offset);
+            assert!(c.length <= SMALL.max as usize);
+            assert!(c.length >= SMALL.min as usize || i == chunks.len() - 1);
+            let range = c.offset as usize..c.offset as usize + c.length;
+            assert_eq!(c.digest, HashImpl::digest(&data[range], HashAlgorithm::SHA256));
+            offset += c.length as u64;
+        }
+        assert_eq!(offset, data.len() as u64);
+    }
+
+    #[test]
+    fn insertion_keeps_most_chunks() {
+        let data = noise(100_000, 2);
+        let mut edited = data[..500].to_vec();
+        edited.extend_from_slice(b"inserted");
+        edited.extend_from_slice(&data[500..]);
+
+        let original = chunk_bytes(&data);
+        let changed = chunk_bytes(&edited);
+        let reference: HashSet<&[u8]> = original.iter().map(|c| c.digest.as_slice()).collect();
+        let shared = shared_with(&reference, &changed);
+        assert!(shared.chunks + 3 >= changed.len(), "{} of {}", shared.chunks, changed.len());
+        assert!(shared.bytes > 90_000);
+    }
+}
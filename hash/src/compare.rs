@@ -0,0 +1,98 @@
+use crate::encoding::constant_time_eq;
+use crate::{bytes_to_hex_string, get_algorithm, HashAlgorithm, HashImpl, OutputStyle};
+use clap::{Arg, ArgMatches, Command};
+
+pub fn command() -> Command<'static> {
+    Command::new("compare")
+        .about("Check whether two files, or two texts, have the same digest. Exits with 0 on a match and 1 on a mismatch")
+        .arg(
+            Arg::new("a")
+                .value_name("A")
+                .help("First file, or text with --text")
+                .required(true)
+        )
+        .arg(
+            Arg::new("b")
+                .value_name("B")
+                .help("Second file, or text with --text")
+                .required(true)
+        )
+        .arg(
+            Arg::new("text")
+                .short('t')
+                .long("text")
+                .help("Treat A and B as texts instead of file paths")
+        )
+}
+
+fn digest_of(input: &str, is_text: bool, algo: HashAlgorithm) -> Vec<u8> {
+    if is_text {
+        return HashImpl::digest(input.as_bytes(), algo);
+    }
+    match std::fs::File::open(input).and_then(|mut f| HashImpl::digest_reader(&mut f, algo)) {
+        Ok(d) => d,
+        Err(err) => {
+            eprintln!("Cannot read file {}: {}", input, err);
+            std::process::exit(exitcode::IOERR);
+        }
+    }
+}
+
+/// Returns whether both inputs have the same digest.
+pub fn run(matches: &ArgMatches) -> bool {
+    let algo = get_algorithm(matches);
+    let is_text = matches.is_present("text");
+
+    let styles: Vec<_> = ["a", "b"]
+        .iter()
+        .map(|name| {
+            let input = matches.value_of(name).unwrap();
+            let digest = digest_of(input, is_text, algo);
+            let mut style = OutputStyle::new();
+            style.set_algorithm(algo);
+            if is_text {
+                style.add_text(input);
+            } else {
+                style.add_file(input);
+            }
+            style.add_hash(&bytes_to_hex_string(&digest));
+            (style, digest)
+        })
+        .collect();
+    let (a, a_digest) = &styles[0];
+    let (b, b_digest) = &styles[1];
+    let matched = constant_time_eq(a_digest, b_digest);
+
+    if matches.is_present("quiet") {
+        return matched;
+    }
+    if matched {
+        println!("{}\n{}\n{}", a.entry_line("MATCH"), b.entry_line("MATCH"), a.hash_line());
+    } else {
+        println!(
+            "{}\n{}\n{}\n{}",
+            a.entry_line("MISMATCH"),
+            a.hash_line(),
+            b.entry_line("MISMATCH"),
+            b.hash_line()
+        );
+    }
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texts_and_files_compare_by_content() {
+        let path = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(path.path(), "hello").unwrap();
+        let file = digest_of(&path.path().to_string_lossy(), false, HashAlgorithm::SHA256);
+
+        let text = digest_of("hello", true, HashAlgorithm::SHA256);
+        assert_eq!(bytes_to_hex_string(&text), "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert!(constant_time_eq(&file, &text));
+        assert!(!constant_time_eq(&text, &digest_of("hello\n", true, HashAlgorithm::SHA256)));
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{BufRead, BufReader, Read};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Recognise a compressed stream from its leading magic bytes.
+    pub fn detect(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if magic.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+}
+
+/// How file inputs are decoded before hashing, as selected by `--decompress`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Decompress {
+    /// Hash the bytes as they are on disk.
+    #[default]
+    Off,
+    /// Decompress when the magic bytes are recognised, otherwise hash as is.
+    Auto,
+    Forced(Compression),
+}
+
+impl Decompress {
+    pub const NAMES: [&'static str; 5] = ["auto", "gzip", "xz", "zstd", "bz2"];
+
+    pub fn from_name(name: Option<&str>) -> Self {
+        match name {
+            None => Decompress::Off,
+            Some("gzip") => Decompress::Forced(Compression::Gzip),
+            Some("xz") => Decompress::Forced(Compression::Xz),
+            Some("zstd") => Decompress::Forced(Compression::Zstd),
+            Some("bz2") => Decompress::Forced(Compression::Bzip2),
+            Some(_) => Decompress::Auto,
+        }
+    }
+}
+
+/// Wrap `reader` so that it yields the decompressed stream. Nothing is written to
+/// disk; the decoder pulls compressed bytes on demand.
+pub fn decoder<'a, R: Read + 'a>(reader: R, mode: Decompress) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let compression = match mode {
+        Decompress::Off => return Ok(Box::new(reader)),
+        Decompress::Forced(c) => c,
+        Decompress::Auto => match Compression::detect(reader.fill_buf()?) {
+            Some(c) => c,
+            None => return Ok(Box::new(reader)),
+        },
+    };
+    Ok(match compression {
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Compression::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const TEXT: &[u8] = b"hello hello hello hello\n";
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Zstd => zstd::encode_all(data, 3).unwrap(),
+            Compression::Bzip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    fn decode(data: &[u8], mode: Decompress) -> Vec<u8> {
+        let mut out = Vec::new();
+        decoder(data, mode).unwrap().read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        for (name, compression) in [
+            ("gzip", Compression::Gzip),
+            ("xz", Compression::Xz),
+            ("zstd", Compression::Zstd),
+            ("bz2", Compression::Bzip2),
+        ] {
+            let compressed = compress(compression, TEXT);
+            assert_eq!(Compression::detect(&compressed), Some(compression));
+            assert_eq!(decode(&compressed, Decompress::Auto), TEXT, "{}", name);
+            assert_eq!(decode(&compressed, Decompress::from_name(Some(name))), TEXT, "{}", name);
+            assert_eq!(decode(&compressed, Decompress::Off), compressed, "{}", name);
+        }
+    }
+
+    #[test]
+    fn reads_concatenated_members() {
+        for compression in [Compression::Gzip, Compression::Xz, Compression::Zstd, Compression::Bzip2] {
+            let mut stream = compress(compression, b"first ");
+            stream.extend(compress(compression, b"second"));
+            assert_eq!(decode(&stream, Decompress::Auto), b"first second", "{:?}", compression);
+        }
+    }
+
+    #[test]
+    fn auto_leaves_plain_input_alone() {
+        assert_eq!(Compression::detect(TEXT), None);
+        assert_eq!(decode(TEXT, Decompress::Auto), TEXT);
+        assert_eq!(decode(b"", Decompress::Auto), b"");
+        assert!(decoder(TEXT, Decompress::Forced(Compression::Gzip))
+            .and_then(|mut r| r.read_to_end(&mut Vec::new()))
+            .is_err());
+    }
+}
@@ -0,0 +1,412 @@
+//! `hash diff A B`: compare two checksum manifests or directories file by file.
+//!
+//! A side is a directory, hashed on the fly, or a manifest in the `sha256sum` format
+//! that `--manifest` writes. Paths of a directory are taken relative to it; `--strip`
+//! drops leading components of manifest paths, for manifests made by other tools or
+//! written from a parent directory, such as `sha256sum release/*` whose paths all start
+//! with `release/`. A file only in B whose digest belongs to a file only in A is
+//! reported as renamed. The algorithm comes from the manifests, through a
+//! `# algo=<name>` line or tagged digests, or from `--md5`, `--sha256` or `--blake3`;
+//! plain 32-byte digests need one of those flags, as they may be SHA256 or BLAKE3.
+//! Exits with 0 when both sides hold the same files, 1 when they differ and above 1 on
+//! errors.
+
+use crate::cache::unescape_path;
+use crate::encoding::{decode_plain_hex, decode_tagged};
+use crate::select::FileFilter;
+use crate::{bytes_to_hex_string, HashAlgorithm, HashImpl};
+use clap::{Arg, ArgMatches, Command};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+pub type Manifest = BTreeMap<String, Vec<u8>>;
+
+pub fn command() -> Command<'static> {
+    Command::new("diff")
+        .about("Compare two manifests or directories: list added, removed, modified and renamed files. Exits with 0 when they match and 1 when they differ")
+        .arg(
+            Arg::new("a")
+                .value_name("A")
+                .help("Old manifest or directory")
+                .required(true)
+        )
+        .arg(
+            Arg::new("b")
+                .value_name("B")
+                .help("New manifest or directory")
+                .required(true)
+        )
+        .arg(
+            Arg::new("exclude")
+                .short('x')
+                .long("exclude")
+                .value_name("pattern")
+                .help("Skip files matching this glob pattern when hashing a directory. Can be provided multiple times")
+                .takes_value(true)
+                .multiple_occurrences(true)
+        )
+        .arg(
+            Arg::new("gitignore")
+                .long("gitignore")
+                .help("Skip files ignored by .gitignore, .ignore and .git/info/exclude, and VCS directories, when hashing a directory")
+        )
+        .arg(
+            Arg::new("strip")
+                .long("strip")
+                .value_name("N")
+                .help("Remove the first N components of every path in a manifest, like patch -p")
+                .takes_value(true)
+                .validator(|s| s.parse::<usize>())
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print the differences as JSON")
+        )
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ManifestDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+    pub renamed: Vec<(String, String)>,
+    pub unchanged: usize,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty() && self.renamed.is_empty()
+    }
+}
+
+/// Comment line that names the algorithm of a manifest's digests, as `# algo=blake3`.
+pub const ALGO_HEADER: &str = "# algo=";
+
+/// A manifest and the algorithm of its digests, when the manifest tells: through an
+/// `ALGO_HEADER` line, through tagged digests, or through the length of MD5 digests.
+/// Untagged 32-byte digests may be SHA256 or BLAKE3, so they leave it unknown.
+#[derive(Clone, Debug, Default)]
+pub struct ParsedManifest {
+    pub entries: Manifest,
+    pub algo: Option<HashAlgorithm>,
+}
+
+/// Parse a `sha256sum` style manifest, accepting the `*` binary marker, escaped lines
+/// and digests written with `--format`. Blank lines and `#` comments are skipped.
+pub fn parse_manifest(path: &str, strip: usize) -> Result<ParsedManifest, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    parse_manifest_bytes(path, &bytes, strip)
+}
+
+/// Parse manifest content already in memory; `path` only names it in errors.
+pub fn parse_manifest_bytes(path: &str, bytes: &[u8], strip: usize) -> Result<ParsedManifest, String> {
+    let text = std::str::from_utf8(bytes).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    let mut manifest = ParsedManifest::default();
+    // Length and line number of the first digest without a tag.
+    let mut untagged: Option<(usize, usize)> = None;
+    let mut settle = |algo: HashAlgorithm, number: usize| match manifest.algo {
+        Some(known) if known != algo => Err(format!("{}:{}: {:?} digest in a manifest of {:?} digests", path, number + 1, algo, known)),
+        _ => {
+            manifest.algo = Some(algo);
+            Ok(())
+        }
+    };
+    let mut entries = Manifest::new();
+    for (number, line) in text.lines().enumerate() {
+        if let Some(name) = line.strip_prefix(ALGO_HEADER) {
+            settle(name.trim().parse().map_err(|e| format!("{}:{}: {}", path, number + 1, e))?, number)?;
+            continue;
+        }
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let parsed = line.split_once(' ').and_then(|(hex, rest)| {
+            let name = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+            let digest = match decode_tagged(hex) {
+                Some((_, algo, bytes)) => (Some(algo), bytes),
+                None => (None, decode_plain_hex(hex)?),
+            };
+            Some((digest, name))
+        });
+        let Some(((algo, digest), name)) = parsed else {
+            return Err(format!("{}:{}: not a '<hash>  <path>' line", path, number + 1));
+        };
+        match (algo, untagged) {
+            (Some(algo), _) => settle(algo, number)?,
+            (None, Some((len, _))) if len != digest.len() => {
+                return Err(format!("{}:{}: {}-byte digest in a manifest of {}-byte digests", path, number + 1, digest.len(), len));
+            }
+            (None, Some(_)) => {}
+            (None, None) => untagged = Some((digest.len(), number)),
+        }
+        let name = if escaped { unescape_path(name) } else { name.to_string() };
+        let name = crate::manifest::normalize_path(&name);
+        let Some(name) = name.splitn(strip + 1, '/').nth(strip) else {
+            return Err(format!("{}:{}: {} has fewer than {} components to strip", path, number + 1, name, strip + 1));
+        };
+        entries.insert(name.to_string(), digest);
+    }
+    manifest.entries = entries;
+    if let Some((len, number)) = untagged {
+        match manifest.algo {
+            Some(algo) if algo.output_len() != len => {
+                return Err(format!("{}:{}: {}-byte digest in a manifest of {:?} digests", path, number + 1, len, algo));
+            }
+            Some(_) => {}
+            None if len == HashAlgorithm::MD5.output_len() => manifest.algo = Some(HashAlgorithm::MD5),
+            None if len == HashAlgorithm::SHA256.output_len() => {}
+            None => return Err(format!("{}:{}: no supported algorithm has {}-byte digests", path, number + 1, len)),
+        }
+    }
+    Ok(manifest)
+}
+
+/// Digest every file below `dir` that passes `filter`, keyed by its path relative to `dir`.
+pub fn hash_dir(dir: &str, algo: HashAlgorithm, filter: &FileFilter) -> Result<Manifest, String> {
+    let fail = |e: std::io::Error| format!("Cannot hash directory {}: {}", dir, e);
+    let mut manifest = Manifest::new();
+    for path in filter.walk(Path::new(dir)).map_err(fail)? {
+        let digest = HashImpl::digest_reader(&mut File::open(&path).map_err(fail)?, algo).map_err(fail)?;
+        let rel = path.strip_prefix(dir).unwrap_or(&path);
+        manifest.insert(rel.to_string_lossy().into_owned(), digest);
+    }
+    Ok(manifest)
+}
+
+/// Compare `a` with `b`. Renames pair files with the same digest, in path order.
+pub fn diff(a: &Manifest, b: &Manifest) -> ManifestDiff {
+    let mut result = ManifestDiff::default();
+    let mut removed_by_digest: HashMap<&[u8], Vec<&str>> = HashMap::new();
+    for (path, digest) in a.iter() {
+        match b.get(path) {
+            Some(other) if other == digest => result.unchanged += 1,
+            Some(_) => result.modified.push(path.clone()),
+            None => removed_by_digest.entry(digest.as_slice()).or_default().push(path),
+        }
+    }
+    for list in removed_by_digest.values_mut() {
+        list.reverse();
+    }
+    for (path, digest) in b.iter().filter(|(path, _)| !a.contains_key(*path)) {
+        match removed_by_digest.get_mut(digest.as_slice()).and_then(|list| list.pop()) {
+            Some(old) => result.renamed.push((old.to_string(), path.clone())),
+            None => result.added.push(path.clone()),
+        }
+    }
+    result.removed = removed_by_digest.into_values().flatten().map(String::from).collect();
+    result.removed.sort();
+    result
+}
+
+/// The algorithm the manifests tell, or the one given on the command line. Fails when
+/// they disagree, or when a manifest holds untagged 32-byte digests and nothing tells
+/// SHA256 from BLAKE3. Without manifests, the algorithm of the command line.
+pub fn pick_algorithm(matches: &ArgMatches, manifests: &[(&str, &ParsedManifest)]) -> Result<HashAlgorithm, String> {
+    let requested = matches.is_present("md5") || matches.is_present("blake3") || matches.is_present("sha256");
+    let mut picked = requested.then(|| crate::get_algorithm(matches));
+    for (name, manifest) in manifests {
+        match (manifest.algo, picked) {
+            (Some(algo), Some(other)) if algo != other => return Err(format!("{} holds {:?} digests, not {:?}", name, algo, other)),
+            (Some(algo), _) => picked = Some(algo),
+            (None, _) => {}
+        }
+    }
+    if let Some(algo) = picked {
+        return Ok(algo);
+    }
+    match manifests.iter().find(|(_, manifest)| !manifest.entries.is_empty()) {
+        Some((name, _)) => Err(format!("{} holds untagged digests that may be SHA256 or BLAKE3, use --sha256 or --blake3", name)),
+        None => Ok(crate::get_algorithm(matches)),
+    }
+}
+
+/// Returns whether both sides hold the same files with the same content.
+pub fn run(matches: &ArgMatches) -> bool {
+    let sides = [matches.value_of("a").unwrap(), matches.value_of("b").unwrap()];
+    let fail = |err: String| -> ! {
+        eprintln!("{}", err);
+        std::process::exit(exitcode::DATAERR);
+    };
+
+    let strip = matches.value_of_t("strip").unwrap_or(0);
+    let mut parsed: Vec<Option<ParsedManifest>> = Vec::new();
+    for side in sides {
+        parsed.push(if Path::new(side).is_dir() {
+            None
+        } else {
+            Some(parse_manifest(side, strip).unwrap_or_else(|e| fail(e)))
+        });
+    }
+    let named: Vec<_> = sides.iter().zip(parsed.iter()).filter_map(|(side, parsed)| Some((*side, parsed.as_ref()?))).collect();
+    let algo = pick_algorithm(matches, &named).unwrap_or_else(|e| fail(e));
+    let filter = FileFilter::from_matches(matches);
+    let manifests: Vec<Manifest> = parsed
+        .into_iter()
+        .zip(sides)
+        .map(|(parsed, side)| match parsed {
+            Some(ParsedManifest { entries: manifest, .. }) => {
+                if let Some((path, digest)) = manifest.iter().find(|(_, d)| d.len() != algo.output_len()) {
+                    fail(format!(
+                        "{}: the digest of {} has {} bytes, but {:?} digests have {}",
+                        side,
+                        path,
+                        digest.len(),
+                        algo,
+                        algo.output_len()
+                    ));
+                }
+                manifest
+            }
+            None => hash_dir(side, algo, &filter).unwrap_or_else(|e| fail(e)),
+        })
+        .collect();
+
+    let result = diff(&manifests[0], &manifests[1]);
+
+    if matches.is_present("json") {
+        let doc = json!({
+            "a": sides[0],
+            "b": sides[1],
+            "algorithm": format!("{:?}", algo),
+            "added": result.added,
+            "removed": result.removed,
+            "modified": result.modified,
+            "renamed": result.renamed.iter().map(|(from, to)| json!({ "from": from, "to": to })).collect::<Vec<_>>(),
+            "unchanged": result.unchanged,
+        });
+        println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+        return result.is_empty();
+    }
+
+    print_changes(&result, &manifests[1]);
+    if !matches.is_present("quiet") {
+        println!(
+            "[SUMMARY] [{} ADDED] [{} REMOVED] [{} MODIFIED] [{} RENAMED] [{} UNCHANGED]",
+            result.added.len(),
+            result.removed.len(),
+            result.modified.len(),
+            result.renamed.len(),
+            result.unchanged
+        );
+    }
+    result.is_empty()
+}
+
+/// Print one line per change, with the new digest of added and modified files.
+pub fn print_changes(result: &ManifestDiff, b: &Manifest) {
+    for path in result.added.iter() {
+        println!("[ADDED] [{}] [{}]", path, bytes_to_hex_string(&b[path]));
+    }
+    for path in result.removed.iter() {
+        println!("[REMOVED] [{}]", path);
+    }
+    for path in result.modified.iter() {
+        println!("[MODIFIED] [{}] [{}]", path, bytes_to_hex_string(&b[path]));
+    }
+    for (from, to) in result.renamed.iter() {
+        println!("[RENAMED] [{}] -> [{}]", from, to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn parse(text: &str, strip: usize) -> Result<Manifest, String> {
+        parse_manifest_bytes("test", text.as_bytes(), strip).map(|m| m.entries)
+    }
+
+    fn algo_of(text: &str) -> Result<Option<HashAlgorithm>, String> {
+        parse_manifest_bytes("test", text.as_bytes(), 0).map(|m| m.algo)
+    }
+
+    fn pick(flags: &[&str], text: &str) -> Result<HashAlgorithm, String> {
+        let matches = crate::build_app().get_matches_from(["hash"].iter().chain(flags).chain(&["diff", "a", "b"]));
+        let manifest = parse_manifest_bytes("test", text.as_bytes(), 0).unwrap();
+        pick_algorithm(matches.subcommand_matches("diff").unwrap(), &[("test", &manifest)])
+    }
+
+    #[test]
+    fn parses_plain_and_tagged_digests() {
+        let text = format!(
+            "# comment\n{hex}  a\n{hex} *b\nsha256:{hex}  c\nsha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=  d\n",
+            hex = EMPTY_SHA256
+        );
+        let manifest = parse(&text, 0).unwrap();
+        let expected = decode_plain_hex(EMPTY_SHA256).unwrap();
+        assert_eq!(manifest.keys().collect::<Vec<_>>(), ["a", "b", "c", "d"]);
+        assert!(manifest.values().all(|d| *d == expected));
+        assert_eq!(algo_of(&text), Ok(Some(HashAlgorithm::SHA256)));
+    }
+
+    #[test]
+    fn rejects_mixed_algorithms() {
+        let md5 = "d41d8cd98f00b204e9800998ecf8427e";
+        assert_eq!(
+            algo_of(&format!("sha256:{}  a\nblake3:{}  b\n", EMPTY_SHA256, EMPTY_SHA256)).unwrap_err(),
+            "test:2: BLAKE3 digest in a manifest of SHA256 digests"
+        );
+        assert_eq!(algo_of(&format!("{}  a\n{}  b\n", md5, EMPTY_SHA256)).unwrap_err(), "test:2: 32-byte digest in a manifest of 16-byte digests");
+        assert_eq!(algo_of(&format!("# algo=md5\n{}  a\n", EMPTY_SHA256)).unwrap_err(), "test:2: 32-byte digest in a manifest of MD5 digests");
+        assert_eq!(algo_of(&format!("{}  a\n", md5)), Ok(Some(HashAlgorithm::MD5)));
+    }
+
+    #[test]
+    fn untagged_32_byte_digests_are_ambiguous() {
+        let untagged = format!("{}  a\n", EMPTY_SHA256);
+        assert_eq!(algo_of(&untagged), Ok(None));
+        assert!(pick(&[], &untagged).unwrap_err().contains("use --sha256 or --blake3"));
+        assert_eq!(pick(&["--blake3"], &untagged), Ok(HashAlgorithm::BLAKE3));
+        let declared = format!("# algo=blake3\n{}", untagged);
+        assert_eq!(pick(&[], &declared), Ok(HashAlgorithm::BLAKE3));
+        assert_eq!(pick(&["--sha256"], &declared).unwrap_err(), "test holds BLAKE3 digests, not SHA256");
+        assert_eq!(pick(&[], ""), Ok(HashAlgorithm::SHA256));
+    }
+
+    #[test]
+    fn unescapes_and_strips_paths() {
+        let text = format!("\\{}  ./dir/new\\nline\n", EMPTY_SHA256);
+        assert!(parse(&text, 0).unwrap().contains_key("dir/new\nline"));
+        assert!(parse(&text, 1).unwrap().contains_key("new\nline"));
+        assert!(parse(&text, 2).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(parse("xyz  a\n", 0).unwrap_err(), "test:1: not a '<hash>  <path>' line");
+        assert!(parse(&format!("{}a\n", EMPTY_SHA256), 0).is_err());
+    }
+
+    #[test]
+    fn pairs_renames_by_digest() {
+        let digest = |b: u8| vec![b; 32];
+        let a: Manifest = [("old".to_string(), digest(1)), ("same".to_string(), digest(2)), ("mod".to_string(), digest(3))].into();
+        let b: Manifest = [("new".to_string(), digest(1)), ("same".to_string(), digest(2)), ("mod".to_string(), digest(4))].into();
+        let result = diff(&a, &b);
+        assert_eq!(result.unchanged, 1);
+        assert_eq!(result.modified, ["mod"]);
+        assert_eq!(result.renamed, [("old".to_string(), "new".to_string())]);
+        assert!(result.added.is_empty() && result.removed.is_empty());
+    }
+
+    #[test]
+    fn directories_diff_against_their_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/empty"), "").unwrap();
+        std::fs::write(dir.path().join("hello"), "hello").unwrap();
+        let tree = hash_dir(&dir.path().to_string_lossy(), HashAlgorithm::SHA256, &FileFilter::default()).unwrap();
+        let manifest = parse(&format!("{}  sub/empty\n{}  hello\n", EMPTY_SHA256, EMPTY_SHA256), 0).unwrap();
+        let result = diff(&manifest, &tree);
+        assert_eq!((result.unchanged, result.modified.as_slice()), (1, ["hello".to_string()].as_slice()));
+    }
+}
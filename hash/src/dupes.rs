@@ -0,0 +1,233 @@
+use crate::{bytes_to_hex_string, get_algorithm, HashAlgorithm, HashImpl};
+use clap::{Arg, ArgMatches, Command};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+/// Number of leading bytes hashed to cheaply split same-sized candidates.
+const PARTIAL_HASH_LEN: u64 = 4096;
+
+pub fn command() -> Command<'static> {
+    Command::new("dupes")
+        .about("Find files with identical content in one or more directories")
+        .arg(
+            Arg::new("dir")
+                .value_name("dir")
+                .help("Directory to walk. Can be provided multiple times, all directories are searched together")
+                .required(true)
+                .multiple_values(true)
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print the duplicate groups as JSON")
+        )
+        .arg(
+            Arg::new("empty")
+                .long("empty")
+                .help("Also report empty files, which are skipped by default")
+        )
+}
+
+#[derive(Clone, Debug)]
+pub struct DupeGroup {
+    pub size: u64,
+    pub hash: String,
+    pub files: Vec<PathBuf>,
+}
+
+fn collect_files(dirs: &[&str], include_empty: bool) -> BTreeMap<u64, Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut by_size: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    for dir in dirs {
+        for entry in WalkDir::new(dir).sort_by_file_name() {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(err) => {
+                    eprintln!("Cannot walk {}: {}", dir, err);
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            // Overlapping directory arguments must not report a file as its own duplicate.
+            let canonical = std::fs::canonicalize(entry.path()).unwrap_or_else(|_| entry.path().to_path_buf());
+            if !seen.insert(canonical) {
+                continue;
+            }
+            let size = match entry.metadata() {
+                Ok(m) => m.len(),
+                Err(err) => {
+                    eprintln!("Cannot read metadata of {}: {}", entry.path().display(), err);
+                    continue;
+                }
+            };
+            if size == 0 && !include_empty {
+                continue;
+            }
+            by_size.entry(size).or_default().push(entry.into_path());
+        }
+    }
+    by_size
+}
+
+fn partial_digest(path: &PathBuf, algo: HashAlgorithm) -> std::io::Result<Vec<u8>> {
+    let mut head = File::open(path)?.take(PARTIAL_HASH_LEN);
+    HashImpl::digest_reader(&mut head, algo)
+}
+
+fn full_digest(path: &PathBuf, algo: HashAlgorithm) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    HashImpl::digest_reader(&mut file, algo)
+}
+
+/// Split `paths` into buckets sharing the same `hasher` result, keeping only buckets
+/// that still hold more than one file.
+fn regroup<F>(paths: Vec<PathBuf>, hasher: F) -> Vec<(Vec<u8>, Vec<PathBuf>)>
+where
+    F: Fn(&PathBuf) -> std::io::Result<Vec<u8>>,
+{
+    let mut buckets: HashMap<Vec<u8>, Vec<PathBuf>> = HashMap::new();
+    for path in paths {
+        match hasher(&path) {
+            Ok(digest) => buckets.entry(digest).or_default().push(path),
+            Err(err) => eprintln!("Cannot read file {}: {}", path.display(), err),
+        }
+    }
+    let mut groups: Vec<_> = buckets.into_iter().filter(|(_, v)| v.len() > 1).collect();
+    groups.sort_by(|a, b| a.1.cmp(&b.1));
+    groups
+}
+
+pub fn find_dupes(dirs: &[&str], algo: HashAlgorithm, include_empty: bool) -> Vec<DupeGroup> {
+    let mut groups = Vec::new();
+    for (size, paths) in collect_files(dirs, include_empty) {
+        if paths.len() < 2 {
+            continue;
+        }
+        let candidates = if size > PARTIAL_HASH_LEN {
+            regroup(paths, |p| partial_digest(p, algo))
+                .into_iter()
+                .map(|(_, v)| v)
+                .collect()
+        } else {
+            // The partial hash would already cover the whole file.
+            vec![paths]
+        };
+        for candidate in candidates {
+            for (digest, files) in regroup(candidate, |p| full_digest(p, algo)) {
+                groups.push(DupeGroup {
+                    size,
+                    hash: bytes_to_hex_string(&digest),
+                    files,
+                });
+            }
+        }
+    }
+    groups
+}
+
+pub fn run(matches: &ArgMatches) {
+    let algo = get_algorithm(matches);
+    let dirs: Vec<&str> = matches.values_of("dir").unwrap().collect();
+    let groups = find_dupes(&dirs, algo, matches.is_present("empty"));
+
+    let redundant: usize = groups.iter().map(|g| g.files.len() - 1).sum();
+    let wasted: u64 = groups.iter().map(|g| g.size * (g.files.len() as u64 - 1)).sum();
+
+    if matches.is_present("json") {
+        let json_groups: Vec<_> = groups
+            .iter()
+            .map(|g| {
+                json!({
+                    "size": g.size,
+                    "hash": g.hash,
+                    "files": g.files.iter().map(|p| p.display().to_string()).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        let doc = json!({
+            "algorithm": format!("{:?}", algo),
+            "groups": json_groups,
+            "redundant_files": redundant,
+            "wasted_bytes": wasted,
+        });
+        println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+        return;
+    }
+
+    if matches.is_present("quiet") {
+        for group in groups.iter() {
+            for file in group.files.iter() {
+                println!("{}", file.display());
+            }
+            println!();
+        }
+        return;
+    }
+
+    let surr_line = "=".repeat(80);
+    for (index, group) in groups.iter().enumerate() {
+        println!("{}", surr_line);
+        println!(
+            "[DUPLICATE GROUP {}] [{} FILES] [{} BYTES]",
+            index + 1,
+            group.files.len(),
+            group.size
+        );
+        println!("[{:?} HASH] [{}]", algo, group.hash);
+        for file in group.files.iter() {
+            println!("    {}", file.display());
+        }
+        println!("{}\n", surr_line);
+    }
+    println!(
+        "{} duplicate groups, {} redundant files, {} wasted bytes",
+        groups.len(),
+        redundant,
+        wasted
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_identical_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        let big = vec![7u8; 10_000];
+        let mut big_other = big.clone();
+        *big_other.last_mut().unwrap() = 8;
+        for (name, content) in [
+            ("a.txt", &b"hello"[..]),
+            ("sub/b.txt", b"hello"),
+            ("c.txt", b"world"),
+            ("big1", &big),
+            ("sub/big2", &big),
+            ("big3", &big_other),
+            ("empty1", b""),
+            ("empty2", b""),
+        ] {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        let root = dir.to_string_lossy().into_owned();
+        let sub = dir.join("sub").to_string_lossy().into_owned();
+
+        // The overlapping second argument must not duplicate anything.
+        let groups = find_dupes(&[&root, &sub], HashAlgorithm::SHA256, false);
+        assert_eq!(groups.len(), 2);
+        assert_eq!((groups[0].size, groups[0].files.clone()), (5, vec![dir.join("a.txt"), dir.join("sub/b.txt")]));
+        assert_eq!(groups[0].hash, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert_eq!((groups[1].size, groups[1].files.clone()), (10_000, vec![dir.join("big1"), dir.join("sub/big2")]));
+
+        let with_empty = find_dupes(&[&root], HashAlgorithm::SHA256, true);
+        assert_eq!(with_empty[0].files, [dir.join("empty1"), dir.join("empty2")]);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{bytes_to_hex_string, hex_to_byte, HashAlgorithm};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use data_encoding::BASE32_NOPAD;
+
+/// Multicodec code of a CIDv1 `raw` block.
+const CID_RAW_CODEC: u64 = 0x55;
+
+/// How a digest is written out, as selected by `--format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum DigestFormat {
+    #[default]
+    Hex,
+    /// Subresource Integrity: `sha256-<base64>`.
+    Sri,
+    /// Multihash (`varint(code) || varint(len) || digest`) in base58btc multibase, `z...`.
+    Multihash,
+    /// CIDv1 of a raw block with this multihash, in base32 multibase, `b...`.
+    Cid,
+    /// OCI/Docker content digest: `sha256:<hex>`.
+    Oci,
+}
+
+impl DigestFormat {
+    pub const NAMES: [&'static str; 5] = ["hex", "sri", "multihash", "cid", "oci"];
+
+    pub fn from_name(name: Option<&str>) -> Self {
+        match name {
+            Some("sri") => DigestFormat::Sri,
+            Some("multihash") => DigestFormat::Multihash,
+            Some("cid") => DigestFormat::Cid,
+            Some("oci") => DigestFormat::Oci,
+            _ => DigestFormat::Hex,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        DigestFormat::NAMES[*self as usize]
+    }
+
+    /// Check that the format has a registered name or code for `algo`.
+    pub fn supports(&self, algo: HashAlgorithm) -> Result<(), String> {
+        let supported = match self {
+            DigestFormat::Sri => sri_name(algo).is_some(),
+            DigestFormat::Oci => oci_name(algo).is_some(),
+            _ => true,
+        };
+        if supported {
+            Ok(())
+        } else {
+            Err(format!("The {} format has no registered name for {:?}", self.name(), algo))
+        }
+    }
+
+    /// Write `digest` in this format. `supports` must have accepted `algo`.
+    pub fn render(&self, digest: &[u8], algo: HashAlgorithm) -> String {
+        match self {
+            DigestFormat::Hex => bytes_to_hex_string(digest),
+            DigestFormat::Sri => format!("{}-{}", sri_name(algo).unwrap_or_default(), STANDARD.encode(digest)),
+            DigestFormat::Oci => format!("{}:{}", oci_name(algo).unwrap_or_default(), bytes_to_hex_string(digest)),
+            DigestFormat::Multihash => format!("z{}", bs58::encode(multihash(digest, algo)).into_string()),
+            DigestFormat::Cid => {
+                let mut cid = Vec::new();
+                push_varint(&mut cid, 1);
+                push_varint(&mut cid, CID_RAW_CODEC);
+                cid.extend_from_slice(&multihash(digest, algo));
+                format!("b{}", BASE32_NOPAD.encode(&cid).to_ascii_lowercase())
+            }
+        }
+    }
+}
+
+/// Hash function names defined by the Subresource Integrity specification.
+fn sri_name(algo: HashAlgorithm) -> Option<&'static str> {
+    match algo {
+        HashAlgorithm::SHA256 => Some("sha256"),
+        _ => None,
+    }
+}
+
+/// Algorithm identifiers registered by the OCI image specification.
+fn oci_name(algo: HashAlgorithm) -> Option<&'static str> {
+    match algo {
+        HashAlgorithm::SHA256 => Some("sha256"),
+        HashAlgorithm::BLAKE3 => Some("blake3"),
+        HashAlgorithm::MD5 => None,
+    }
+}
+
+/// Code of the algorithm in the multicodec table.
+fn multihash_code(algo: HashAlgorithm) -> u64 {
+    match algo {
+        HashAlgorithm::MD5 => 0xd5,
+        HashAlgorithm::SHA256 => 0x12,
+        HashAlgorithm::BLAKE3 => 0x1e,
+    }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..63).step_by(7) {
+        let (&b, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn multihash(digest: &[u8], algo: HashAlgorithm) -> Vec<u8> {
+    let mut out = Vec::new();
+    push_varint(&mut out, multihash_code(algo));
+    push_varint(&mut out, digest.len() as u64);
+    out.extend_from_slice(digest);
+    out
+}
+
+fn parse_multihash(mut bytes: &[u8]) -> Option<(HashAlgorithm, Vec<u8>)> {
+    let code = read_varint(&mut bytes)?;
+    let len = read_varint(&mut bytes)?;
+    let algo = HashAlgorithm::ALL.into_iter().find(|a| multihash_code(*a) == code)?;
+    (len as usize == algo.output_len() && bytes.len() == algo.output_len()).then(|| (algo, bytes.to_vec()))
+}
+
+fn parse_cid(mut bytes: &[u8]) -> Option<(HashAlgorithm, Vec<u8>)> {
+    if read_varint(&mut bytes)? != 1 {
+        return None;
+    }
+    read_varint(&mut bytes)?;
+    parse_multihash(bytes)
+}
+
+/// Decode a digest that names its algorithm: SRI, OCI, multihash (base58btc `z`, hex
+/// `f` or a bare CIDv0 `Qm...`) or a CIDv1 in base32.
+pub fn decode_tagged(digest: &str) -> Option<(DigestFormat, HashAlgorithm, Vec<u8>)> {
+    let digest = digest.trim();
+    for algo in HashAlgorithm::ALL {
+        if let Some(b64) = sri_name(algo).and_then(|name| digest.strip_prefix(name)?.strip_prefix('-')) {
+            let bytes = STANDARD.decode(b64).ok().filter(|b| b.len() == algo.output_len())?;
+            return Some((DigestFormat::Sri, algo, bytes));
+        }
+        if let Some(hex) = oci_name(algo).and_then(|name| digest.strip_prefix(name)?.strip_prefix(':')) {
+            let bytes = decode_plain_hex(hex).filter(|b| b.len() == algo.output_len())?;
+            return Some((DigestFormat::Oci, algo, bytes));
+        }
+    }
+    let (format, tagged) = match digest.split_at_checked(1)? {
+        ("z", rest) => (DigestFormat::Multihash, parse_multihash(&bs58::decode(rest).into_vec().ok()?)),
+        ("f", rest) => (DigestFormat::Multihash, parse_multihash(&decode_plain_hex(rest)?)),
+        ("b", rest) => {
+            let bytes = BASE32_NOPAD.decode(rest.to_ascii_uppercase().as_bytes()).ok()?;
+            (DigestFormat::Cid, parse_cid(&bytes))
+        }
+        ("Q", _) if digest.starts_with("Qm") => (DigestFormat::Multihash, parse_multihash(&bs58::decode(digest).into_vec().ok()?)),
+        _ => return None,
+    };
+    tagged.map(|(algo, bytes)| (format, algo, bytes))
+}
+
+pub fn decode_plain_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Decode a digest written as plain hex (optionally `0x`-prefixed), as a `--hex` style
+/// byte list such as `0x19 0xab`, as standard/URL-safe base64 with or without
+/// padding, or in one of the `DigestFormat`s. Self-describing formats are tried first,
+/// then plain hex wins when a string is valid in several encodings.
+pub fn decode_digest(digest: &str) -> Option<Vec<u8>> {
+    if let Some((_, _, bytes)) = decode_tagged(digest) {
+        return Some(bytes);
+    }
+    let digest = digest.trim();
+    if digest.contains(|c: char| c.is_whitespace() || c == ',') {
+        return digest
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| hex_to_byte(s).ok())
+            .collect();
+    }
+    let hex = digest
+        .strip_prefix("0x")
+        .or_else(|| digest.strip_prefix("0X"))
+        .unwrap_or(digest);
+    if let Some(bytes) = decode_plain_hex(hex) {
+        return Some(bytes);
+    }
+    [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|engine| engine.decode(digest).ok())
+        .filter(|bytes| !bytes.is_empty())
+}
+
+/// Compare two digests without an early exit, so the time taken does not reveal how
+/// many leading bytes matched.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HashImpl;
+
+    fn hello_world() -> Vec<u8> {
+        HashImpl::digest(b"hello world", HashAlgorithm::SHA256)
+    }
+
+    #[test]
+    fn renders_known_formats() {
+        let digest = hello_world();
+        let render = |format: DigestFormat| format.render(&digest, HashAlgorithm::SHA256);
+        assert_eq!(render(DigestFormat::Hex), "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+        assert_eq!(render(DigestFormat::Sri), "sha256-uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=");
+        assert_eq!(render(DigestFormat::Oci), "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+        assert_eq!(render(DigestFormat::Multihash), "zQmaozNR7DZHQK1ZcU9p7QdrshMvXqWK6gpu5rmrkPdT3L4");
+        // The CID the IPFS documentation gives for "hello world" added as a raw block.
+        assert_eq!(render(DigestFormat::Cid), "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e");
+    }
+
+    #[test]
+    fn tagged_formats_round_trip() {
+        for algo in HashAlgorithm::ALL {
+            let digest = HashImpl::digest(b"abc", algo);
+            for name in DigestFormat::NAMES {
+                let format = DigestFormat::from_name(Some(name));
+                if format == DigestFormat::Hex || format.supports(algo).is_err() {
+                    continue;
+                }
+                let rendered = format.render(&digest, algo);
+                assert_eq!(decode_tagged(&rendered), Some((format, algo, digest.clone())), "{}", rendered);
+                assert_eq!(decode_digest(&rendered), Some(digest.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        assert!(DigestFormat::Sri.supports(HashAlgorithm::BLAKE3).is_err());
+        assert!(DigestFormat::Oci.supports(HashAlgorithm::MD5).is_err());
+        assert!(DigestFormat::Multihash.supports(HashAlgorithm::MD5).is_ok());
+    }
+
+    #[test]
+    fn decodes_plain_encodings() {
+        let digest = hello_world();
+        let hex = bytes_to_hex_string(&digest);
+        assert_eq!(decode_digest(&hex), Some(digest.clone()));
+        assert_eq!(decode_digest(&format!("0x{}", hex.to_uppercase())), Some(digest.clone()));
+        assert_eq!(decode_digest(&STANDARD.encode(&digest)), Some(digest.clone()));
+        assert_eq!(decode_digest(&URL_SAFE_NO_PAD.encode(&digest)), Some(digest.clone()));
+        assert_eq!(decode_digest("0x19 0xab,0x01"), Some(vec![0x19, 0xab, 0x01]));
+        assert_eq!(decode_plain_hex("abc"), None);
+        assert_eq!(decode_plain_hex(""), None);
+        assert_eq!(decode_tagged("sha256:abcd"), None);
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX as u64] {
+            let mut out = Vec::new();
+            push_varint(&mut out, value);
+            assert_eq!(read_varint(&mut out.as_slice()), Some(value));
+        }
+        let mut out = Vec::new();
+        push_varint(&mut out, 300);
+        assert_eq!(out, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Framing of `--update` inputs, so that the digest commits to where each input starts
+//! and ends: without it `"ab","c"` and `"a","bc"` hash the same bytes.
+//!
+//! * `u32le`, `u32be`, `u64le`, `u64be`: every input is prefixed with its byte length;
+//! * `delimiter`: the `--delimiter` bytes go between inputs. This is only unambiguous
+//!   when no input contains the delimiter;
+//! * `tuple`: the encoding of NIST SP 800-185 TupleHash, with the selected algorithm in
+//!   place of cSHAKE: every input `X` becomes `left_encode(bitlen(X)) || X`, and
+//!   `right_encode(bitlen(digest))` is appended before each digest is taken.
+
+use crate::{parse_hex_bytes, HashAlgorithm};
+use clap::ArgMatches;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Plain concatenation.
+    #[default]
+    None,
+    LengthPrefix { width: usize, big_endian: bool },
+    Delimiter(Vec<u8>),
+    Tuple,
+}
+
+/// The minimal big-endian bytes of `x`, at least one.
+fn be_bytes(x: u64) -> Vec<u8> {
+    let bytes = x.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    bytes[skip..].to_vec()
+}
+
+fn left_encode(x: u64) -> Vec<u8> {
+    let bytes = be_bytes(x);
+    let mut out = vec![bytes.len() as u8];
+    out.extend_from_slice(&bytes);
+    out
+}
+
+fn right_encode(x: u64) -> Vec<u8> {
+    let mut out = be_bytes(x);
+    out.push(out.len() as u8);
+    out
+}
+
+impl Framing {
+    pub const NAMES: [&'static str; 7] = ["none", "u32le", "u32be", "u64le", "u64be", "delimiter", "tuple"];
+
+    /// The framing selected by `--frame` and `--delimiter`, exiting on a bad delimiter.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.value_of("frame") {
+            Some("u32le") => Framing::LengthPrefix { width: 4, big_endian: false },
+            Some("u32be") => Framing::LengthPrefix { width: 4, big_endian: true },
+            Some("u64le") => Framing::LengthPrefix { width: 8, big_endian: false },
+            Some("u64be") => Framing::LengthPrefix { width: 8, big_endian: true },
+            Some("tuple") => Framing::Tuple,
+            Some("delimiter") => {
+                let delimiter = matches.value_of("delimiter").unwrap_or_default();
+                if !matches.is_present("hex") {
+                    return Framing::Delimiter(delimiter.as_bytes().to_vec());
+                }
+                match parse_hex_bytes(delimiter) {
+                    Ok(bytes) => Framing::Delimiter(bytes),
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        std::process::exit(exitcode::DATAERR);
+                    }
+                }
+            }
+            _ => Framing::None,
+        }
+    }
+
+    /// The bytes fed to the hasher for the input at position `index`.
+    pub fn encode(&self, index: usize, input: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = match self {
+            Framing::None => Vec::new(),
+            Framing::LengthPrefix { width, big_endian } => {
+                let len = input.len() as u64;
+                if *width == 4 && len > u32::MAX as u64 {
+                    return Err(format!("Input of {} bytes is too long for a u32 length prefix", len));
+                }
+                let bytes = if *big_endian { len.to_be_bytes() } else { len.to_le_bytes() };
+                if *big_endian {
+                    bytes[8 - width..].to_vec()
+                } else {
+                    bytes[..*width].to_vec()
+                }
+            }
+            Framing::Delimiter(delimiter) if index > 0 => delimiter.clone(),
+            Framing::Delimiter(_) => Vec::new(),
+            Framing::Tuple => left_encode(input.len() as u64 * 8),
+        };
+        out.extend_from_slice(input);
+        Ok(out)
+    }
+
+    /// Bytes appended to everything hashed so far when a digest is taken.
+    pub fn suffix(&self, algo: HashAlgorithm) -> Vec<u8> {
+        match self {
+            Framing::Tuple => right_encode(algo.output_len() as u64 * 8),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(framing: &Framing, inputs: &[&[u8]]) -> Vec<u8> {
+        inputs.iter().enumerate().flat_map(|(i, input)| framing.encode(i, input).unwrap()).collect()
+    }
+
+    #[test]
+    fn sp800_185_encodings() {
+        assert_eq!(left_encode(0), [1, 0]);
+        assert_eq!(left_encode(256), [2, 1, 0]);
+        assert_eq!(right_encode(0), [0, 1]);
+        assert_eq!(right_encode(256), [1, 0, 2]);
+        assert_eq!(Framing::Tuple.suffix(HashAlgorithm::SHA256), [1, 0, 2]);
+        assert!(Framing::None.suffix(HashAlgorithm::SHA256).is_empty());
+    }
+
+    #[test]
+    fn length_prefixes() {
+        let u32le = Framing::LengthPrefix { width: 4, big_endian: false };
+        let u64be = Framing::LengthPrefix { width: 8, big_endian: true };
+        assert_eq!(u32le.encode(0, b"abc").unwrap(), b"\x03\0\0\0abc");
+        assert_eq!(u64be.encode(3, b"abc").unwrap(), b"\0\0\0\0\0\0\0\x03abc");
+        assert_eq!(Framing::Tuple.encode(0, b"abc").unwrap(), b"\x01\x18abc");
+    }
+
+    #[test]
+    fn delimiter_goes_between_inputs() {
+        let framing = Framing::Delimiter(b"\n".to_vec());
+        assert_eq!(framed(&framing, &[b"a", b"b", b"c"]), b"a\nb\nc");
+    }
+
+    #[test]
+    fn framing_separates_splits() {
+        for framing in [Framing::LengthPrefix { width: 4, big_endian: true }, Framing::Delimiter(b",".to_vec()), Framing::Tuple] {
+            assert_ne!(framed(&framing, &[b"ab", b"c"]), framed(&framing, &[b"a", b"bc"]), "{:?}", framing);
+        }
+        assert_eq!(framed(&Framing::None, &[b"ab", b"c"]), framed(&Framing::None, &[b"a", b"bc"]));
+    }
+}
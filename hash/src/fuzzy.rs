@@ -0,0 +1,229 @@
+//! `hash fuzzy` and `hash similarity`: similarity digests with ssdeep and TLSH.
+//!
+//! Unlike `HashAlgorithm` digests these are not bytes to compare for equality but
+//! strings to score against each other, so they live beside `HashInput` rather than in
+//! it. `similarity` takes files, texts with `--text`, or digests printed by `fuzzy`,
+//! `ssdeep` or `tlsh`, and prints a score from 0 (unrelated) to 100 (identical). For
+//! TLSH, which only defines a distance, the score is this tool's own rescale of it.
+
+use crate::ssdeep::{self, Ssdeep};
+use crate::tlsh::{self, Tlsh};
+use crate::OutputStyle;
+use clap::{Arg, ArgMatches, Command};
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FuzzyAlgorithm {
+    Ssdeep,
+    Tlsh,
+}
+
+enum FuzzyState {
+    Ssdeep(Box<Ssdeep>),
+    Tlsh(Box<Tlsh>),
+}
+
+impl FuzzyAlgorithm {
+    pub const NAMES: [&'static str; 2] = ["ssdeep", "tlsh"];
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.value_of("algorithm") {
+            Some("tlsh") => FuzzyAlgorithm::Tlsh,
+            _ => FuzzyAlgorithm::Ssdeep,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            FuzzyAlgorithm::Ssdeep => "SSDEEP",
+            FuzzyAlgorithm::Tlsh => "TLSH",
+        }
+    }
+
+    fn state(&self) -> FuzzyState {
+        match self {
+            FuzzyAlgorithm::Ssdeep => FuzzyState::Ssdeep(Box::new(Ssdeep::new())),
+            FuzzyAlgorithm::Tlsh => FuzzyState::Tlsh(Box::new(Tlsh::new())),
+        }
+    }
+
+    fn is_digest(&self, digest: &str) -> bool {
+        match self {
+            FuzzyAlgorithm::Ssdeep => ssdeep::is_digest(digest),
+            FuzzyAlgorithm::Tlsh => tlsh::is_digest(digest),
+        }
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Result<u32, String> {
+        match self {
+            FuzzyAlgorithm::Ssdeep => ssdeep::compare(a, b),
+            FuzzyAlgorithm::Tlsh => tlsh::compare(a, b),
+        }
+    }
+
+    pub fn digest_reader<R: Read>(&self, reader: &mut R) -> std::io::Result<Result<String, String>> {
+        let mut state = self.state();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => match &mut state {
+                    FuzzyState::Ssdeep(s) => s.update(&buf[..n]),
+                    FuzzyState::Tlsh(t) => t.update(&buf[..n]),
+                },
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(match state {
+            FuzzyState::Ssdeep(s) => s.digest(),
+            FuzzyState::Tlsh(t) => t.digest(),
+        })
+    }
+}
+
+fn algorithm_arg() -> Arg<'static> {
+    Arg::new("algorithm")
+        .short('a')
+        .long("algorithm")
+        .value_name("name")
+        .help("Similarity digest, ssdeep by default")
+        .takes_value(true)
+        .possible_values(FuzzyAlgorithm::NAMES)
+}
+
+fn text_arg() -> Arg<'static> {
+    Arg::new("text")
+        .short('t')
+        .long("text")
+        .help("Treat the inputs as texts instead of file paths")
+}
+
+pub fn fuzzy_command() -> Command<'static> {
+    Command::new("fuzzy")
+        .about("Print ssdeep or TLSH similarity digests, for use with similarity")
+        .arg(
+            Arg::new("inputs")
+                .value_name("file")
+                .help("Files to hash, or texts with --text")
+                .required(true)
+                .multiple_values(true)
+        )
+        .arg(algorithm_arg())
+        .arg(text_arg())
+}
+
+pub fn similarity_command() -> Command<'static> {
+    Command::new("similarity")
+        .about("Print how similar two inputs are, from 0 to 100, using ssdeep or TLSH. The ssdeep score is the one ssdeep -d prints. TLSH only defines a distance, so its score is a linear rescale of it, not a reference metric: 100 at distance 0, 0 at 300 or more")
+        .arg(
+            Arg::new("a")
+                .value_name("A")
+                .help("First file, text with --text, or a digest printed by fuzzy")
+                .required(true)
+        )
+        .arg(
+            Arg::new("b")
+                .value_name("B")
+                .help("Second file, text with --text, or a digest printed by fuzzy")
+                .required(true)
+        )
+        .arg(algorithm_arg())
+        .arg(text_arg())
+        .arg(
+            Arg::new("threshold")
+                .long("threshold")
+                .value_name("score")
+                .help("Exit with 1 when the score is below this. For TLSH, a score of S means a distance of at most 3 * (100 - S)")
+                .takes_value(true)
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(n) if n <= 100 => Ok(()),
+                    _ => Err("should be a number from 0 to 100".to_string()),
+                })
+        )
+}
+
+fn fail(code: i32, err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(code);
+}
+
+fn digest_of(input: &str, is_text: bool, algo: FuzzyAlgorithm) -> String {
+    let digest = if is_text {
+        algo.digest_reader(&mut input.as_bytes()).unwrap()
+    } else {
+        File::open(input)
+            .and_then(|mut f| algo.digest_reader(&mut f))
+            .unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot read file {}: {}", input, e)))
+    };
+    digest.unwrap_or_else(|e| fail(exitcode::DATAERR, format!("{}: {}", input, e)))
+}
+
+fn style_for(input: &str, is_text: bool) -> OutputStyle {
+    let mut style = OutputStyle::new();
+    if is_text {
+        style.add_text(input);
+    } else {
+        style.add_file(input);
+    }
+    style
+}
+
+pub fn run_fuzzy(matches: &ArgMatches) {
+    let algo = FuzzyAlgorithm::from_matches(matches);
+    let is_text = matches.is_present("text");
+    for input in matches.values_of("inputs").unwrap() {
+        let digest = digest_of(input, is_text, algo);
+        if matches.is_present("quiet") {
+            println!("{}", digest);
+            continue;
+        }
+        let mut style = style_for(input, is_text);
+        style.set_label(algo.label());
+        style.add_hash(&digest);
+        println!("{}", style.summary("COMPUTE"));
+    }
+}
+
+/// Returns false when the score is below `--threshold`.
+pub fn run_similarity(matches: &ArgMatches) -> bool {
+    let algo = FuzzyAlgorithm::from_matches(matches);
+    let is_text = matches.is_present("text");
+
+    let mut digests = Vec::new();
+    let mut lines = Vec::new();
+    for name in ["a", "b"] {
+        let input = matches.value_of(name).unwrap();
+        // A digest is only taken as such when no file has that name.
+        let given = !is_text && !std::path::Path::new(input).exists() && algo.is_digest(input);
+        let digest = if given { input.to_string() } else { digest_of(input, is_text, algo) };
+        let mut style = style_for(input, is_text);
+        if given {
+            style.add_digest(input);
+        }
+        style.set_label(algo.label());
+        style.add_hash(&digest);
+        lines.push(format!("{}\n{}", style.entry_line("COMPARE"), style.hash_line()));
+        digests.push(digest);
+    }
+
+    let score = algo
+        .compare(&digests[0], &digests[1])
+        .unwrap_or_else(|e| fail(exitcode::DATAERR, e));
+    if matches.is_present("quiet") {
+        println!("{}", score);
+    } else {
+        for line in lines {
+            println!("{}", line);
+        }
+        if algo == FuzzyAlgorithm::Tlsh {
+            println!("[TLSH DISTANCE] [{}]", tlsh::distance(&digests[0], &digests[1]).unwrap());
+        }
+        println!("[{} SIMILARITY] [{}]", algo.label(), score);
+    }
+    match matches.value_of_t::<u32>("threshold") {
+        Ok(threshold) => score >= threshold,
+        Err(_) => true,
+    }
+}
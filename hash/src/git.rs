@@ -0,0 +1,227 @@
+//! Git object ids for `--git`, matching `git hash-object` and `git write-tree`.
+//!
+//! An object id is `H(type || " " || decimal(len) || 0x00 || content)`, with `H` being
+//! SHA-1 or, for repositories created with `--object-format=sha256`, SHA-256.
+//!
+//! * blob: the file content, or the target of a symbolic link;
+//! * tree: one `mode || " " || name || 0x00 || raw id` entry per child, with modes
+//!   `100644`, `100755` (any executable bit), `120000` (symlink) and `40000` (tree).
+//!   Entries are sorted by name bytes, a directory sorting as if its name ended in
+//!   `/`. Empty directories are left out and `.git` is skipped, as git does.
+
+use crate::select::FileFilter;
+use clap::ArgMatches;
+use sha2::digest::DynDigest;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectFormat {
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// The object format selected by `--git`: SHA-1 unless `--sha256` is given.
+    pub fn from_matches(matches: &ArgMatches) -> Option<Self> {
+        if !matches.is_present("git") {
+            None
+        } else if matches.is_present("sha256") {
+            Some(ObjectFormat::Sha256)
+        } else {
+            Some(ObjectFormat::Sha1)
+        }
+    }
+
+    /// Label for the hash line of an output record.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "GIT SHA1",
+            ObjectFormat::Sha256 => "GIT SHA256",
+        }
+    }
+
+    fn hasher(&self) -> Box<dyn DynDigest> {
+        match self {
+            ObjectFormat::Sha1 => Box::new(sha1::Sha1::default()),
+            ObjectFormat::Sha256 => Box::new(sha2::Sha256::default()),
+        }
+    }
+}
+
+fn object_id(kind: &str, len: u64, content: &mut dyn Read, format: ObjectFormat) -> std::io::Result<Vec<u8>> {
+    let mut hasher = format.hasher();
+    hasher.update(format!("{} {}\0", kind, len).as_bytes());
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut total = 0u64;
+    loop {
+        let n = content.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        total += n as u64;
+        hasher.update(&buf[..n]);
+    }
+    if total != len {
+        // The header already committed to the old length.
+        return Err(std::io::Error::other("file changed size while it was hashed"));
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+pub fn blob_id(content: &[u8], format: ObjectFormat) -> Vec<u8> {
+    object_id("blob", content.len() as u64, &mut &content[..], format).unwrap_or_default()
+}
+
+/// Blob id of a file, streamed without reading it into memory.
+pub fn blob_id_file(path: &Path, format: ObjectFormat) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    object_id("blob", len, &mut file, format)
+}
+
+fn os_bytes(s: &OsStr) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        s.as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        s.to_string_lossy().as_bytes().to_vec()
+    }
+}
+
+fn file_mode(metadata: &std::fs::Metadata) -> &'static str {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return "100755";
+        }
+    }
+    let _ = metadata;
+    "100644"
+}
+
+struct TreeEntry {
+    /// The name, with a trailing `/` for directories.
+    sort_key: Vec<u8>,
+    mode: &'static str,
+    name: Vec<u8>,
+    id: Vec<u8>,
+}
+
+/// Tree id of `dir` holding only the files and links in `included`. Returns `None`
+/// for a directory with nothing to store, which git does not record.
+fn tree_id_of(dir: &Path, included: &HashSet<PathBuf>, format: ObjectFormat) -> std::io::Result<Option<Vec<u8>>> {
+    let mut entries = Vec::new();
+    for child in std::fs::read_dir(dir)? {
+        let child = child?;
+        let path = child.path();
+        let name = os_bytes(&child.file_name());
+        let metadata = std::fs::symlink_metadata(&path)?;
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            if name == b".git" {
+                continue;
+            }
+            if let Some(id) = tree_id_of(&path, included, format)? {
+                let mut sort_key = name.clone();
+                sort_key.push(b'/');
+                entries.push(TreeEntry { sort_key, mode: "40000", name, id });
+            }
+        } else if included.contains(&path) {
+            let (mode, id) = if file_type.is_symlink() {
+                ("120000", blob_id(&os_bytes(std::fs::read_link(&path)?.as_os_str()), format))
+            } else {
+                (file_mode(&metadata), blob_id_file(&path, format)?)
+            };
+            entries.push(TreeEntry { sort_key: name.clone(), mode, name, id });
+        }
+    }
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    entries.sort_by(|a, b| a.sort_key.cmp(&b.sort_key));
+
+    let mut body = Vec::new();
+    for entry in entries {
+        body.extend_from_slice(entry.mode.as_bytes());
+        body.push(b' ');
+        body.extend_from_slice(&entry.name);
+        body.push(0);
+        body.extend_from_slice(&entry.id);
+    }
+    Ok(Some(object_id("tree", body.len() as u64, &mut &body[..], format)?))
+}
+
+/// Tree id of `dir` as `git write-tree` would compute it after adding every file
+/// that passes `filter`.
+pub fn tree_id(dir: &Path, format: ObjectFormat, filter: &FileFilter) -> std::io::Result<Vec<u8>> {
+    let included: HashSet<PathBuf> = filter.walk_with_links(dir)?.into_iter().collect();
+    match tree_id_of(dir, &included, format)? {
+        Some(id) => Ok(id),
+        // The well-known empty tree.
+        None => object_id("tree", 0, &mut &[][..], format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes_to_hex_string;
+
+    fn blob(content: &str, format: ObjectFormat) -> String {
+        bytes_to_hex_string(&blob_id(content.as_bytes(), format))
+    }
+
+    #[test]
+    fn known_blob_ids() {
+        assert_eq!(blob("", ObjectFormat::Sha1), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        assert_eq!(blob("hello\n", ObjectFormat::Sha1), "ce013625030ba8dba906f756967f9e9ca394464a");
+        assert_eq!(blob("", ObjectFormat::Sha256), "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813");
+        assert_eq!(blob("hello\n", ObjectFormat::Sha256), "2cf8d83d9ee29543b34a87727421fdecb7e3f3a183d337639025de576db9ebb4");
+    }
+
+    #[test]
+    fn empty_tree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir(dir.join("nothing")).unwrap();
+        let id = |format| bytes_to_hex_string(&tree_id(dir, format, &FileFilter::default()).unwrap());
+        assert_eq!(id(ObjectFormat::Sha1), "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+        assert_eq!(id(ObjectFormat::Sha256), "6ef19b41225c5369f1c104d45d8d85efa9b057b53b14b4b9b939dd74decc5321");
+    }
+
+    // The ids `git add -A && git write-tree` gives for the same files.
+    #[cfg(unix)]
+    #[test]
+    fn tree_matches_git_write_tree() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join("a"), "hello\n").unwrap();
+        std::fs::write(dir.join("sub/b"), "").unwrap();
+        // Sorts before "sub", which git compares as "sub/".
+        std::fs::write(dir.join("sub.txt"), "x").unwrap();
+        std::fs::write(dir.join("x.sh"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(dir.join("x.sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("a", dir.join("link")).unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ignored").unwrap();
+
+        let id = |format| bytes_to_hex_string(&tree_id(dir, format, &FileFilter::default()).unwrap());
+        assert_eq!(id(ObjectFormat::Sha1), "95efb3ddaa79867546d58579411477ac64d57fd6");
+        assert_eq!(id(ObjectFormat::Sha256), "1a2f740079d438a5e2c0643ce9f5a62455f8ca83b3d5ae6a3141deb2a424c8bd");
+        assert_eq!(
+            blob_id_file(&dir.join("a"), ObjectFormat::Sha1).unwrap(),
+            blob_id(b"hello\n", ObjectFormat::Sha1)
+        );
+    }
+}
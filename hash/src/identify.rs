@@ -0,0 +1,334 @@
+//! `hash identify`: guess which algorithm produced a digest from its encoding and
+//! length, and optionally confirm the guess by hashing a known input.
+
+use crate::encoding::{decode_plain_hex, decode_tagged};
+use crate::{hex_to_byte, kdf, HashAlgorithm, HashImpl};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use clap::{Arg, ArgMatches, Command};
+use serde_json::json;
+
+/// Common algorithms this tool cannot compute, by digest length in bytes. They are
+/// listed so a guess is not limited to what `hash` happens to implement.
+const OTHER_ALGORITHMS: [(usize, &[&str]); 8] = [
+    (4, &["CRC-32", "Adler-32"]),
+    (8, &["CRC-64", "xxHash64", "SipHash"]),
+    (16, &["MD4", "NTLM", "RIPEMD-128", "xxHash128"]),
+    (20, &["SHA-1", "RIPEMD-160"]),
+    (28, &["SHA-224", "SHA3-224"]),
+    (32, &["SHA3-256", "BLAKE2s-256", "Keccak-256"]),
+    (48, &["SHA-384", "SHA3-384"]),
+    (64, &["SHA-512", "SHA3-512", "BLAKE2b-512", "Whirlpool"]),
+];
+
+pub fn command() -> Command<'static> {
+    Command::new("identify")
+        .about("List the algorithms that fit a digest's encoding and length, optionally checking each one against a known input")
+        .arg(
+            Arg::new("digest")
+                .value_name("digest")
+                .help("Digest as hex, base64, a '0x19 0xab' byte list, an SRI, multihash, CID or OCI digest, or a PHC/crypt string such as '$argon2id$...'")
+                .required(true)
+        )
+        .arg(
+            Arg::new("file")
+                .short('f')
+                .long("file")
+                .value_name("file")
+                .help("Hash this file with every supported candidate to find the one that matches")
+                .takes_value(true)
+                .conflicts_with("text")
+        )
+        .arg(
+            Arg::new("text")
+                .short('t')
+                .long("text")
+                .value_name("text")
+                .help("Hash this text with every supported candidate, or check it as the password of a PHC string")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print the candidates as JSON")
+        )
+}
+
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    /// How the digest was read: `hex`, `base64`, one of the `DigestFormat` names,
+    /// `phc`, `bcrypt` or `crypt`.
+    pub encoding: &'static str,
+    pub len: Option<usize>,
+    pub algorithm: String,
+    /// Extra details such as the cost parameters of a PHC string.
+    pub params: Option<String>,
+    pub supported: bool,
+    /// Whether the known input hashes to the digest, if one was given and the
+    /// candidate is supported.
+    pub matched: Option<bool>,
+    algo: Option<HashAlgorithm>,
+    bytes: Vec<u8>,
+}
+
+enum Known<'a> {
+    Text(&'a str),
+    File(&'a str),
+}
+
+/// The byte strings a plain digest could stand for, one per encoding that accepts it.
+pub fn readings(digest: &str) -> Vec<(&'static str, Vec<u8>)> {
+    let digest = digest.trim();
+    if digest.contains(|c: char| c.is_whitespace() || c == ',') {
+        let bytes: Option<Vec<u8>> = digest
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| hex_to_byte(s).ok())
+            .collect();
+        return bytes.map(|b| vec![("hex", b)]).unwrap_or_default();
+    }
+    let mut readings = Vec::new();
+    let hex = digest
+        .strip_prefix("0x")
+        .or_else(|| digest.strip_prefix("0X"))
+        .unwrap_or(digest);
+    if let Some(bytes) = decode_plain_hex(hex) {
+        readings.push(("hex", bytes));
+    }
+    if let Some(bytes) = [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|engine| engine.decode(digest).ok())
+        .filter(|bytes| !bytes.is_empty())
+    {
+        readings.push(("base64", bytes));
+    }
+    readings
+}
+
+fn modular_candidate(digest: &str) -> Option<Candidate> {
+    let mut fields = digest.strip_prefix('$')?.split('$');
+    let id = fields.next()?;
+    let rest: Vec<&str> = fields.collect();
+    let (encoding, algorithm, params, supported) = match id {
+        "2a" | "2b" | "2x" | "2y" => ("bcrypt", format!("bcrypt-{}", id), rest.first().map(|c| format!("cost={}", c)), true),
+        "1" => ("crypt", "md5-crypt".to_string(), None, false),
+        "5" => ("crypt", "sha256-crypt".to_string(), None, false),
+        "6" => ("crypt", "sha512-crypt".to_string(), None, false),
+        "argon2id" | "argon2i" | "argon2d" | "scrypt" | "pbkdf2-sha256" | "pbkdf2-sha512" => {
+            // PHC: $id[$v=version][$params]$salt$hash
+            let params = rest.iter().filter(|f| f.contains('=')).copied().collect::<Vec<_>>().join(",");
+            ("phc", id.to_string(), Some(params).filter(|p| !p.is_empty()), true)
+        }
+        _ => ("phc", id.to_string(), None, false),
+    };
+    Some(Candidate {
+        encoding,
+        len: None,
+        algorithm,
+        params,
+        supported,
+        matched: None,
+        algo: None,
+        bytes: Vec::new(),
+    })
+}
+
+/// Every algorithm that fits `digest`, supported ones first within each encoding.
+pub fn candidates(digest: &str) -> Vec<Candidate> {
+    if digest.starts_with('$') {
+        return modular_candidate(digest).into_iter().collect();
+    }
+    if let Some((format, algo, bytes)) = decode_tagged(digest) {
+        return vec![Candidate {
+            encoding: format.name(),
+            len: Some(bytes.len()),
+            algorithm: format!("{:?}", algo),
+            params: None,
+            supported: true,
+            matched: None,
+            algo: Some(algo),
+            bytes,
+        }];
+    }
+    let mut candidates = Vec::new();
+    for (encoding, bytes) in readings(digest) {
+        let len = bytes.len();
+        for algo in HashAlgorithm::ALL.iter().filter(|a| a.output_len() == len) {
+            candidates.push(Candidate {
+                encoding,
+                len: Some(len),
+                algorithm: format!("{:?}", algo),
+                params: None,
+                supported: true,
+                matched: None,
+                algo: Some(*algo),
+                bytes: bytes.clone(),
+            });
+        }
+        for (_, names) in OTHER_ALGORITHMS.iter().filter(|(l, _)| *l == len) {
+            candidates.extend(names.iter().map(|name| Candidate {
+                encoding,
+                len: Some(len),
+                algorithm: name.to_string(),
+                params: None,
+                supported: false,
+                matched: None,
+                algo: None,
+                bytes: Vec::new(),
+            }));
+        }
+    }
+    candidates
+}
+
+fn known_bytes(known: &Known) -> Vec<u8> {
+    match known {
+        Known::Text(text) => text.as_bytes().to_vec(),
+        Known::File(file) => std::fs::read(file).unwrap_or_else(|err| {
+            eprintln!("Cannot read file {}: {}", file, err);
+            std::process::exit(exitcode::IOERR);
+        }),
+    }
+}
+
+fn check(candidate: &mut Candidate, digest: &str, input: &[u8]) {
+    if !candidate.supported {
+        return;
+    }
+    candidate.matched = Some(match candidate.algo {
+        Some(algo) => HashImpl::digest(input, algo) == candidate.bytes,
+        None => kdf::verify_password(input, digest.trim()),
+    });
+}
+
+fn candidate_line(c: &Candidate) -> String {
+    let mut line = match c.len {
+        Some(len) => format!("[{} {} BYTES] [{}]", c.encoding.to_uppercase(), len, c.algorithm),
+        None => format!("[{}] [{}]", c.encoding.to_uppercase(), c.algorithm),
+    };
+    if let Some(params) = &c.params {
+        line.push_str(&format!(" [{}]", params));
+    }
+    match (c.supported, c.matched) {
+        (false, _) => line.push_str(" [NOT SUPPORTED]"),
+        (true, Some(true)) => line.push_str(" [MATCH]"),
+        (true, Some(false)) => line.push_str(" [MISMATCH]"),
+        (true, None) => {}
+    }
+    line
+}
+
+/// Returns false when a known input was given and no candidate reproduced the digest.
+pub fn run(matches: &ArgMatches) -> bool {
+    let digest = matches.value_of("digest").unwrap();
+    let known = match (matches.value_of("text"), matches.value_of("file")) {
+        (Some(text), _) => Some(Known::Text(text)),
+        (_, Some(file)) => Some(Known::File(file)),
+        _ => None,
+    };
+
+    let mut candidates = candidates(digest);
+    if candidates.is_empty() {
+        eprintln!("Cannot decode '{}' as hex, base64 or a PHC string", digest);
+        std::process::exit(exitcode::DATAERR);
+    }
+    if let Some(known) = &known {
+        let input = known_bytes(known);
+        for c in candidates.iter_mut() {
+            check(c, digest, &input);
+        }
+    }
+    let found = known.is_none() || candidates.iter().any(|c| c.matched == Some(true));
+
+    if matches.is_present("json") {
+        let list: Vec<_> = candidates
+            .iter()
+            .map(|c| {
+                json!({
+                    "encoding": c.encoding,
+                    "bytes": c.len,
+                    "algorithm": c.algorithm,
+                    "params": c.params,
+                    "supported": c.supported,
+                    "matched": c.matched,
+                })
+            })
+            .collect();
+        let doc = json!({ "digest": digest, "candidates": list });
+        println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+    } else if matches.is_present("quiet") {
+        for c in candidates.iter().filter(|c| c.matched.unwrap_or(known.is_none())) {
+            println!("{}", c.algorithm);
+        }
+    } else {
+        for c in candidates.iter() {
+            println!("{}", candidate_line(c));
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn names(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|c| c.algorithm.as_str()).collect()
+    }
+
+    #[test]
+    fn guesses_by_length() {
+        let md5 = candidates(EMPTY_MD5);
+        assert_eq!(names(&md5), ["MD5", "MD4", "NTLM", "RIPEMD-128", "xxHash128"]);
+        assert!(md5.iter().all(|c| c.encoding == "hex" && c.len == Some(16)));
+
+        let sha256 = candidates(EMPTY_SHA256);
+        assert_eq!(&names(&sha256)[..2], ["SHA256", "BLAKE3"]);
+        assert!(sha256[..2].iter().all(|c| c.supported) && !sha256[2].supported);
+    }
+
+    #[test]
+    fn confirms_with_known_input() {
+        let mut sha256 = candidates(EMPTY_SHA256);
+        for candidate in sha256.iter_mut() {
+            check(candidate, EMPTY_SHA256, b"");
+        }
+        assert_eq!(sha256[0].matched, Some(true));
+        assert_eq!(sha256[1].matched, Some(false));
+        assert_eq!(sha256[2].matched, None);
+    }
+
+    #[test]
+    fn reads_hex_and_base64() {
+        assert_eq!(readings("0xDEADBEEF"), [("hex", vec![0xde, 0xad, 0xbe, 0xef])]);
+        assert_eq!(readings("0xde 0xad,0xbe 0xef"), [("hex", vec![0xde, 0xad, 0xbe, 0xef])]);
+        // Valid as both hex and base64.
+        let both = readings("abcd");
+        assert_eq!(both, [("hex", vec![0xab, 0xcd]), ("base64", vec![0x69, 0xb7, 0x1d])]);
+        assert!(readings("not a digest!").is_empty());
+    }
+
+    #[test]
+    fn recognises_tagged_formats() {
+        let sri = candidates("sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=");
+        assert_eq!((sri[0].encoding, sri[0].algorithm.as_str()), ("sri", "SHA256"));
+        let oci = candidates("sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!((oci.len(), oci[0].encoding, oci[0].algorithm.as_str()), (1, "oci", "SHA256"));
+    }
+
+    #[test]
+    fn recognises_modular_formats() {
+        let bcrypt = candidates("$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW");
+        assert_eq!(bcrypt[0].algorithm, "bcrypt-2b");
+        assert_eq!(bcrypt[0].params.as_deref(), Some("cost=12"));
+
+        let argon2 = candidates("$argon2id$v=19$m=65536,t=3,p=4$c2FsdHNhbHQ$aGFzaA");
+        assert_eq!((argon2[0].encoding, argon2[0].supported), ("phc", true));
+        assert_eq!(argon2[0].params.as_deref(), Some("v=19,m=65536,t=3,p=4"));
+
+        assert!(!candidates("$6$salt$hash")[0].supported);
+    }
+}
@@ -0,0 +1,219 @@
+//! `hash --interactive`: a line-oriented REPL that prints the digest of every line
+//! typed, as text or as hex bytes. Lines starting with `:` are commands, a leading
+//! `\:` hashes a line that itself starts with a colon.
+
+use crate::{get_algorithm, parse_hex_bytes, HashAlgorithm, HashImpl, OutputStyle};
+use clap::ArgMatches;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::PathBuf;
+
+const HELP: &str = "\
+Type text (or hex bytes such as '0x19 0xab' with :hex on) to print its digest.
+  :algo [md5|sha256|blake3]   show or switch the algorithm
+  :hex [on|off]               show or switch hex input
+  :update [on|off]            show or switch updating one digest with every line
+  :reset                      start a new digest for :update
+  :help                       print this help
+  :quit                       leave, as does Ctrl-D
+Start a line with '\\:' to hash text beginning with a colon.";
+
+struct Session {
+    algo: HashAlgorithm,
+    hex: bool,
+    update: bool,
+    hasher: HashImpl,
+    print_hash_only: bool,
+}
+
+fn parse_switch(arg: &str) -> Option<bool> {
+    match arg {
+        "on" | "1" | "true" | "yes" => Some(true),
+        "off" | "0" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+impl Session {
+    fn prompt(&self) -> String {
+        let mut prompt = self.algo.flag_name().to_string();
+        if self.hex {
+            prompt.push_str(" hex");
+        }
+        if self.update {
+            prompt.push_str(" update");
+        }
+        prompt.push_str("> ");
+        prompt
+    }
+
+    /// Run a `:command`. Returns false when the session should end.
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let arg = words.next();
+        match (name, arg) {
+            (":quit" | ":q" | ":exit", _) => return false,
+            (":help" | ":h" | ":?", _) => println!("{}", HELP),
+            (":algo", None) => println!("algo {}", self.algo.flag_name()),
+            (":algo", Some(arg)) => match arg.parse() {
+                Ok(algo) => {
+                    self.algo = algo;
+                    self.hasher = HashImpl::new();
+                }
+                Err(err) => eprintln!("{}, expected one of md5, sha256, blake3", err),
+            },
+            (":hex", None) => println!("hex {}", on_off(self.hex)),
+            (":update", None) => println!("update {}", on_off(self.update)),
+            (":hex" | ":update", Some(arg)) => match parse_switch(arg) {
+                Some(value) if name == ":hex" => self.hex = value,
+                Some(value) => {
+                    self.update = value;
+                    self.hasher = HashImpl::new();
+                }
+                None => eprintln!("Expected 'on' or 'off', got '{}'", arg),
+            },
+            (":reset", _) => self.hasher = HashImpl::new(),
+            _ => eprintln!("Unknown command '{}', type :help for the list", name),
+        }
+        true
+    }
+
+    fn hash_line(&mut self, line: &str) {
+        let bytes = if self.hex {
+            match parse_hex_bytes(line) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return;
+                }
+            }
+        } else {
+            line.as_bytes().to_vec()
+        };
+        let digest = if self.update {
+            self.hasher.update(&bytes);
+            self.hasher.hex_digest(self.algo)
+        } else {
+            HashImpl::hex_digest_input(&bytes, self.algo)
+        };
+        if self.print_hash_only {
+            println!("{}", digest);
+        } else {
+            let mut style = OutputStyle::new();
+            style.set_algorithm(self.algo);
+            style.add_hash(&digest);
+            println!("{}", style.hash_line());
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".hash_history"))
+}
+
+pub fn run(matches: &ArgMatches) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(e) => e,
+        Err(err) => {
+            eprintln!("Cannot start interactive mode: {}", err);
+            std::process::exit(exitcode::OSERR);
+        }
+    };
+    // Lines typed may be secrets, so they only outlive the session when asked for.
+    let history = if matches.is_present("history") { history_path() } else { None };
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session.
+        let _ = editor.load_history(path);
+    }
+
+    let mut session = Session {
+        algo: get_algorithm(matches),
+        hex: matches.is_present("hex"),
+        update: matches.is_present("update"),
+        hasher: HashImpl::new(),
+        print_hash_only: matches.is_present("quiet"),
+    };
+    if !session.print_hash_only {
+        println!("Type :help for commands, Ctrl-D to quit.");
+    }
+
+    loop {
+        let line = match editor.readline(&session.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Cannot read input: {}", err);
+                break;
+            }
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        if line.starts_with(':') {
+            if !session.command(line.trim()) {
+                break;
+            }
+        } else {
+            session.hash_line(line.strip_prefix('\\').filter(|l| l.starts_with(':')).unwrap_or(&line));
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(err) = editor.save_history(path) {
+            eprintln!("Cannot save history to {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session { algo: HashAlgorithm::SHA256, hex: false, update: false, hasher: HashImpl::new(), print_hash_only: true }
+    }
+
+    #[test]
+    fn commands_switch_modes() {
+        let mut session = session();
+        assert_eq!(session.prompt(), "sha256> ");
+        assert!(session.command(":algo blake3"));
+        assert!(session.command(":hex on"));
+        assert!(session.command(":update yes"));
+        assert_eq!(session.prompt(), "blake3 hex update> ");
+        assert!(session.command(":algo crc32"));
+        assert!(session.command(":hex maybe"));
+        assert_eq!(session.algo, HashAlgorithm::BLAKE3);
+        assert!(session.hex);
+        assert!(!session.command(":quit"));
+        assert!(!session.command(":q now"));
+    }
+
+    #[test]
+    fn update_mode_accumulates_lines() {
+        let mut session = session();
+        session.command(":update on");
+        session.hash_line("ab");
+        session.hash_line("c");
+        assert_eq!(session.hasher.hex_digest(HashAlgorithm::SHA256), HashImpl::hex_digest_input(b"abc", HashAlgorithm::SHA256));
+        session.command(":reset");
+        assert_eq!(session.hasher.hex_digest(HashAlgorithm::SHA256), HashImpl::hex_digest_input(b"", HashAlgorithm::SHA256));
+    }
+
+    #[test]
+    fn switches() {
+        assert_eq!(parse_switch("on"), Some(true));
+        assert_eq!(parse_switch("0"), Some(false));
+        assert_eq!(parse_switch("maybe"), None);
+    }
+}
@@ -0,0 +1,310 @@
+//! `hash kdf`: password hashing with Argon2, scrypt, PBKDF2 and bcrypt.
+//!
+//! Argon2, scrypt and PBKDF2 hashes are printed as PHC strings
+//! (`$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`). bcrypt has no PHC form and uses
+//! its own `$2b$<cost>$<salt><hash>` string, which is what every bcrypt library reads.
+
+use crate::{parse_hex_bytes, EXIT_MISMATCH};
+use argon2::password_hash::{Ident, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use clap::{Arg, ArgMatches, Command};
+use pbkdf2::Pbkdf2;
+use scrypt::Scrypt;
+use std::io::BufRead;
+
+const ALGORITHMS: [&str; 7] = ["argon2id", "argon2i", "argon2d", "scrypt", "pbkdf2-sha256", "pbkdf2-sha512", "bcrypt"];
+
+pub fn command() -> Command<'static> {
+    Command::new("kdf")
+        .about("Hash a password with Argon2, scrypt, PBKDF2 or bcrypt and print the PHC string, or check a password against one")
+        .arg(
+            Arg::new("password")
+                .value_name("password")
+                .help("Password to hash. Read from the first line of stdin when omitted, which keeps it out of the process list")
+        )
+        .arg(
+            Arg::new("algorithm")
+                .short('a')
+                .long("algorithm")
+                .value_name("name")
+                .help("Password hashing algorithm, argon2id by default")
+                .takes_value(true)
+                .possible_values(ALGORITHMS)
+        )
+        .arg(
+            Arg::new("salt")
+                .short('s')
+                .long("salt")
+                .value_name("salt")
+                .help("Salt to use instead of 16 random bytes. bcrypt needs exactly 16 bytes, Argon2 at least 8")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("hex")
+                .short('H')
+                .long("hex")
+                .help("Treat the password and salt as hex strings, e.g. '0x19 0xab 0xcd 0xef'")
+        )
+        .arg(
+            Arg::new("rounds")
+                .short('r')
+                .long("rounds")
+                .value_name("n")
+                .help("Iterations: Argon2 time cost (default 2), PBKDF2 rounds (default 600000) or bcrypt cost (default 12)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("memory")
+                .long("memory")
+                .value_name("KiB")
+                .help("Argon2 memory cost in KiB (default 19456)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("parallelism")
+                .short('p')
+                .long("parallelism")
+                .value_name("n")
+                .help("Argon2 lanes or scrypt parallelization (default 1)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("log-n")
+                .long("log-n")
+                .value_name("n")
+                .help("scrypt cost, as the base 2 logarithm of N (default 17)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("block-size")
+                .long("block-size")
+                .value_name("r")
+                .help("scrypt block size r (default 8)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("length")
+                .short('l')
+                .long("length")
+                .value_name("bytes")
+                .help("Length of the derived key in bytes, not used by bcrypt (default 32)")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .value_name("hash")
+                .help("Check the password against this PHC or bcrypt string instead of hashing it. Exits with 1 on a mismatch")
+                .takes_value(true)
+                .conflicts_with_all(&["algorithm", "salt", "rounds", "memory", "parallelism", "log-n", "block-size", "length"])
+        )
+}
+
+fn input_bytes(value: &str, hex: bool) -> Vec<u8> {
+    if !hex {
+        return value.as_bytes().to_vec();
+    }
+    match parse_hex_bytes(value) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(exitcode::DATAERR);
+        }
+    }
+}
+
+pub fn read_password() -> String {
+    let mut line = String::new();
+    if let Err(err) = std::io::stdin().lock().read_line(&mut line) {
+        eprintln!("Cannot read password from stdin: {}", err);
+        std::process::exit(exitcode::IOERR);
+    }
+    line.trim_end_matches(['\r', '\n']).to_string()
+}
+
+fn number<T: std::str::FromStr>(matches: &ArgMatches, name: &str, default: T) -> T {
+    match matches.value_of(name) {
+        None => default,
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid --{} '{}', should be a number", name, value);
+            std::process::exit(exitcode::USAGE);
+        }),
+    }
+}
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("Cannot hash password: {}", err);
+    std::process::exit(exitcode::DATAERR);
+}
+
+fn hash_password(matches: &ArgMatches, password: &[u8], salt: &[u8]) -> String {
+    let algorithm = matches.value_of("algorithm").unwrap_or("argon2id");
+    let length: usize = number(matches, "length", 32);
+    let parallelism: u32 = number(matches, "parallelism", 1);
+
+    if algorithm == "bcrypt" {
+        let salt: [u8; 16] = salt
+            .try_into()
+            .unwrap_or_else(|_| fail(format!("bcrypt needs a 16 byte salt, got {}", salt.len())));
+        return match bcrypt::hash_with_salt(password, number(matches, "rounds", 12), salt) {
+            Ok(parts) => parts.format_for_version(bcrypt::Version::TwoB),
+            Err(err) => fail(err),
+        };
+    }
+
+    let salt = SaltString::encode_b64(salt).unwrap_or_else(|err| fail(err));
+    let result = match algorithm {
+        "scrypt" => {
+            let params = scrypt::Params::new(
+                number(matches, "log-n", 17),
+                number(matches, "block-size", 8),
+                parallelism,
+                length,
+            )
+            .unwrap_or_else(|err| fail(err));
+            Scrypt.hash_password_customized(password, None, None, params, &salt)
+        }
+        "pbkdf2-sha256" | "pbkdf2-sha512" => {
+            let params = pbkdf2::Params {
+                rounds: number(matches, "rounds", 600_000),
+                output_length: length,
+            };
+            let ident = Ident::new(algorithm).unwrap_or_else(|err| fail(err));
+            Pbkdf2.hash_password_customized(password, Some(ident), None, params, &salt)
+        }
+        _ => {
+            let variant = match algorithm {
+                "argon2i" => argon2::Algorithm::Argon2i,
+                "argon2d" => argon2::Algorithm::Argon2d,
+                _ => argon2::Algorithm::Argon2id,
+            };
+            let params = argon2::Params::new(
+                number(matches, "memory", 19456),
+                number(matches, "rounds", 2),
+                parallelism,
+                Some(length),
+            )
+            .unwrap_or_else(|err| fail(err));
+            Argon2::new(variant, argon2::Version::V0x13, params).hash_password(password, &salt)
+        }
+    };
+    match result {
+        Ok(hash) => hash.to_string(),
+        Err(err) => fail(err),
+    }
+}
+
+/// Whether `password` matches the PHC or bcrypt string `hash`.
+pub fn verify_password(password: &[u8], hash: &str) -> bool {
+    if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash).unwrap_or_else(|err| {
+            eprintln!("Invalid bcrypt hash '{}': {}", hash, err);
+            std::process::exit(exitcode::DATAERR);
+        });
+    }
+    let parsed = PasswordHash::new(hash).unwrap_or_else(|err| {
+        eprintln!("Invalid PHC string '{}': {}", hash, err);
+        std::process::exit(exitcode::DATAERR);
+    });
+    let hashers: [&dyn PasswordVerifier; 3] = [&Argon2::default(), &Scrypt, &Pbkdf2];
+    match parsed.verify_password(&hashers, password) {
+        Ok(()) => true,
+        Err(argon2::password_hash::Error::Password) => false,
+        Err(err) => {
+            eprintln!("Cannot verify '{}': {}", hash, err);
+            std::process::exit(exitcode::DATAERR);
+        }
+    }
+}
+
+pub fn run(matches: &ArgMatches) {
+    let hex = matches.is_present("hex");
+    let password = match matches.value_of("password") {
+        Some(p) => input_bytes(p, hex),
+        None => input_bytes(&read_password(), hex),
+    };
+
+    if let Some(hash) = matches.value_of("verify") {
+        let matched = verify_password(&password, hash);
+        if !matches.is_present("quiet") {
+            let algorithm = hash.split('$').nth(1).unwrap_or_default();
+            println!("[{} KDF] [{}]", if matched { "MATCH" } else { "MISMATCH" }, algorithm);
+        }
+        if !matched {
+            std::process::exit(EXIT_MISMATCH);
+        }
+        return;
+    }
+
+    let salt = match matches.value_of("salt") {
+        Some(s) => input_bytes(s, hex),
+        None => {
+            let mut salt = vec![0u8; 16];
+            if let Err(err) = getrandom::getrandom(&mut salt) {
+                fail(err);
+            }
+            salt
+        }
+    };
+    println!("{}", hash_password(matches, &password, &salt));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD_NO_PAD;
+    use base64::Engine;
+
+    fn hash(args: &[&str], password: &str, salt: &str) -> String {
+        let matches = command().get_matches_from(std::iter::once("kdf").chain(args.iter().copied()));
+        hash_password(&matches, password.as_bytes(), salt.as_bytes())
+    }
+
+    /// The derived key of a PHC string, as hex.
+    fn derived_key(phc: &str) -> String {
+        crate::bytes_to_hex_string(&STANDARD_NO_PAD.decode(phc.rsplit('$').next().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn pbkdf2_rfc7914_vector() {
+        let phc = hash(&["-a", "pbkdf2-sha256", "-r", "1", "-l", "64"], "passwd", "salt");
+        assert!(phc.starts_with("$pbkdf2-sha256$i=1,l=64$c2FsdA$"), "{}", phc);
+        assert_eq!(
+            derived_key(&phc),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+             49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+        assert!(verify_password(b"passwd", &phc));
+        assert!(!verify_password(b"password", &phc));
+    }
+
+    #[test]
+    fn scrypt_rfc7914_vector() {
+        let phc = hash(&["-a", "scrypt", "--log-n", "10", "--block-size", "8", "-p", "16", "-l", "64"], "password", "NaCl");
+        assert_eq!(
+            derived_key(&phc),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+             2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640"
+        );
+        assert!(verify_password(b"password", &phc));
+    }
+
+    #[test]
+    fn argon2_reference_cli_vector() {
+        // `echo -n password | argon2 somesalt -t 2 -m 16 -p 4 -l 24` from the reference
+        // implementation's README.
+        let phc = hash(&["-a", "argon2i", "-r", "2", "--memory", "65536", "-p", "4", "-l", "24"], "password", "somesalt");
+        assert_eq!(phc, "$argon2i$v=19$m=65536,t=2,p=4$c29tZXNhbHQ$RdescudvJCsgt3ub+b+dWRWJTmaaJObG");
+        assert!(verify_password(b"password", &phc));
+        assert!(!verify_password(b"passwore", &phc));
+    }
+
+    #[test]
+    fn bcrypt_openwall_vector() {
+        assert!(verify_password(b"U*U", "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW"));
+        assert!(!verify_password(b"U*V", "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW"));
+        let hashed = hash(&["-a", "bcrypt", "-r", "4"], "secret", "0123456789abcdef");
+        assert!(hashed.starts_with("$2b$04$"));
+        assert!(verify_password(b"secret", &hashed));
+    }
+}
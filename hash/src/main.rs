@@ -1,6 +1,39 @@
+mod archive;
+mod bench;
+mod cache;
+mod blocks;
+mod chunks;
+mod compare;
+mod decompress;
+mod diff;
+mod dupes;
+mod encoding;
+mod framing;
+mod fuzzy;
+mod git;
+mod identify;
+mod interactive;
+mod kdf;
+mod manifest;
+mod normalize;
+mod progress;
+mod select;
+mod selftest;
+mod sign;
+mod ssdeep;
+mod store;
+mod template;
+mod tlsh;
+mod tree;
+mod watch;
+
 use clap::{AppSettings, Arg, ArgMatches, Command};
+use decompress::Decompress;
+use progress::{Progress, ProgressReader};
 use sha2::Digest;
 use std::fmt::{Debug, Display, Formatter};
+use std::io::Read;
+use std::path::Path;
 use std::string::String;
 
 fn build_app() -> Command<'static> {
@@ -9,17 +42,19 @@ fn build_app() -> Command<'static> {
         .version("1.0.0")
         .about("Print string or file checksums.")
         .setting(AppSettings::DeriveDisplayOrder)
-        .override_usage("hash --[md5|sha256|blake3] --text <text>\n    hash --[md5|sha256|blake3] --file <path>")
+        .override_usage("hash --[md5|sha256|blake3] --text <text>\n    hash --[md5|sha256|blake3] --file <path>\n    hash --[md5|sha256|blake3] --tree <dir>\n    hash --[md5|sha256|blake3] dupes <dir>...\n    hash --[md5|sha256|blake3] bench\n    hash --[md5|sha256|blake3] compare <A> <B>\n    hash --[md5|sha256|blake3] block-diff <A> <B>\n    hash --[md5|sha256|blake3] chunks <file>...\n    hash --[md5|sha256|blake3] archive <archive>\n    hash --[md5|sha256|blake3] --interactive\n    hash kdf [password]\n    hash identify <digest>\n    hash --[md5|sha256|blake3] diff <A> <B>\n    hash keygen\n    hash --[md5|sha256|blake3] sign --secret-key <file> <manifest>\n    hash verify-sig --public-key <file> <manifest>\n    hash --[md5|sha256|blake3] store [add <file>...|get <digest>|verify]\n    hash fuzzy [-a ssdeep|tlsh] <file>...\n    hash similarity [-a ssdeep|tlsh] <A> <B>")
         .arg(
             Arg::new("sha256")
                 .short('S')
                 .long("sha256")
+                .global(true)
                 .help("Compute the hash using sha256 algorithm (Default)")
         )
         .arg(
             Arg::new("md5")
                 .short('M')
                 .long("md5")
+                .global(true)
                 .help("Compute the hash using md5 algorithm")
                 .conflicts_with_all(&["sha256", "blake3"])
         )
@@ -27,6 +62,7 @@ fn build_app() -> Command<'static> {
             Arg::new("blake3")
                 .short('B')
                 .long("blake3")
+                .global(true)
                 .help("Compute the hash using blake3 algorithm")
                 .conflicts_with_all(&["md5", "sha256"])
         )
@@ -44,9 +80,97 @@ fn build_app() -> Command<'static> {
                 .short('f')
                 .long("file")
                 .value_name("file")
-                .help("Compute the hash of this file. Can be provided multiple times, compute the hash of each file")
+                .help("Compute the hash of this file. Can be provided multiple times, compute the hash of each file. Glob patterns such as 'src/**/*.rs' are expanded, quote them to keep the shell from doing it")
+                .takes_value(true)
+                .multiple_occurrences(true)
+        )
+        .arg(
+            Arg::new("exclude")
+                .short('x')
+                .long("exclude")
+                .value_name("pattern")
+                .help("Skip files matching this glob pattern, e.g. '*.o' or 'target'. A pattern without a slash matches at any depth. Can be provided multiple times")
+                .takes_value(true)
+                .multiple_occurrences(true)
+        )
+        .arg(
+            Arg::new("gitignore")
+                .long("gitignore")
+                .help("When expanding globs and listing trees, skip files ignored by .gitignore, .ignore and .git/info/exclude, and VCS directories such as .git")
+        )
+        .arg(
+            Arg::new("tree")
+                .short('T')
+                .long("tree")
+                .value_name("dir")
+                .help("Compute the Merkle hash of this directory tree, covering names, contents, executable bits and symlink targets. Can be provided multiple times")
                 .takes_value(true)
                 .multiple_occurrences(true)
+                .conflicts_with_all(&["update", "hex"])
+        )
+        .arg(
+            Arg::new("subdirs")
+                .long("subdirs")
+                .help("With --tree, also print the hash of every subdirectory")
+                .requires("tree")
+        )
+        .arg(
+            Arg::new("watch")
+                .short('w')
+                .long("watch")
+                .help("After the first run, keep watching the files and trees and print a new record whenever their content changes")
+                .conflicts_with("update")
+        )
+        .arg(
+            Arg::new("debounce")
+                .long("debounce")
+                .value_name("ms")
+                .help("With --watch, wait until an input has been unchanged for this many milliseconds before hashing it again")
+                .takes_value(true)
+                .default_value("300")
+        )
+        .arg(
+            Arg::new("block-size")
+                .short('b')
+                .long("block-size")
+                .value_name("size")
+                .help("Print the digest of each block of this size in every file, with its offset, e.g. 4096, 64K or 1M")
+                .takes_value(true)
+                .validator(|s| parse_size(s).ok_or("should be a positive size such as 4096, 64K or 1M"))
+                .conflicts_with_all(&["update", "expect", "watch", "decompress"])
+        )
+        .arg(
+            Arg::new("block-root")
+                .long("block-root")
+                .help("With --block-size, also print a root hash over all block digests")
+                .requires("block-size")
+        )
+        .arg(
+            Arg::new("git")
+                .short('g')
+                .long("git")
+                .help("Hash texts and files as git blobs and --tree directories as git trees, giving the ids of git hash-object and git write-tree. Uses SHA-1, or the SHA-256 object format with --sha256")
+                .conflicts_with_all(&["md5", "blake3", "update", "hex", "normalize", "decompress", "block-size", "manifest", "format", "subdirs", "cache", "watch"])
+        )
+        .arg(
+            Arg::new("cache")
+                .long("cache")
+                .value_name("path")
+                .help("Keep file digests in this cache file and only re-hash files whose size, mtime, ctime or inode changed. One cache can serve several algorithms")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("verify-cache")
+                .long("verify-cache")
+                .help("With --cache, re-hash every file, warn about cached digests that turn out stale, and refresh the cache")
+                .requires("cache")
+        )
+        .arg(
+            Arg::new("manifest")
+                .short('m')
+                .long("manifest")
+                .help("Print a checksum manifest, one '<hash>  <path>' line per file, listing every file below each --tree with its path relative to the tree")
+                .conflicts_with_all(&["text", "update", "expect", "block-size", "watch"])
         )
         .arg(
             Arg::new("update")
@@ -54,18 +178,126 @@ fn build_app() -> Command<'static> {
                 .long("update")
                 .help("Instead of computing the hash of each text/file, update on each of them, and print the finalized digest")
         )
+        .arg(
+            Arg::new("frame")
+                .long("frame")
+                .value_name("framing")
+                .help("With --update, mark where each input starts so that 'ab','c' and 'a','bc' differ: a u32le, u32be, u64le or u64be length prefix, a --delimiter between inputs, or tuple for a TupleHash-style encoding")
+                .takes_value(true)
+                .possible_values(framing::Framing::NAMES)
+                .requires("update")
+        )
+        .arg(
+            Arg::new("delimiter")
+                .long("delimiter")
+                .value_name("bytes")
+                .help("Bytes to put between inputs with --frame delimiter, as text or, with --hex, as hex")
+                .takes_value(true)
+                .required_if_eq("frame", "delimiter")
+                .requires("frame")
+        )
         .arg(
             Arg::new("hex")
                 .short('H')
                 .long("hex")
                 .help("Treat the text or file content as hex strings, e.g. '0x19 0xab 0xcd 0xef'")
         )
+        .arg(
+            Arg::new("normalize")
+                .short('N')
+                .long("normalize")
+                .value_name("steps")
+                .help("Normalise text and file content before hashing: crlf (CRLF to LF), nfc or nfkc (Unicode form), trim (trailing whitespace on each line), strip-newline (one final line ending) or final-newline. Comma separated, steps always run in this order")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_value_delimiter(true)
+                .require_value_delimiter(true)
+                .possible_values(normalize::Normalize::NAMES)
+                .conflicts_with_all(&["hex", "block-size"])
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("format")
+                .help("Write digests as hex (default), sri ('sha256-<base64>'), multihash (base58btc 'z...'), cid (CIDv1 of a raw block, 'b...') or oci ('sha256:<hex>'). --expect accepts all of them")
+                .takes_value(true)
+                .possible_values(encoding::DigestFormat::NAMES)
+                .conflicts_with_all(&["manifest", "block-size", "watch"])
+        )
+        .arg(
+            Arg::new("expect")
+                .short('e')
+                .long("expect")
+                .value_name("digest")
+                .help("Check the digest against this one, given as hex or base64, and print a verdict instead of the hash. Exits with 1 on a mismatch")
+                .takes_value(true)
+                .conflicts_with("watch")
+        )
+        .arg(
+            Arg::new("interactive")
+                .short('i')
+                .long("interactive")
+                .help("Start a prompt that prints the digest of every line typed. Commands such as ':algo md5' and ':hex on' switch settings, ':help' lists them")
+                .conflicts_with_all(&["text", "file", "tree", "watch", "expect", "manifest", "normalize"])
+        )
+        .arg(
+            Arg::new("history")
+                .long("history")
+                .help("With --interactive, load and save the lines typed in ~/.hash_history. Off by default, since lines may hold secrets")
+                .requires("interactive")
+        )
+        .arg(
+            Arg::new("self-test")
+                .long("self-test")
+                .help("Check every algorithm against published known-answer test vectors and exit, non-zero on any mismatch")
+        )
+        .arg(
+            Arg::new("decompress")
+                .long("decompress")
+                .value_name("format")
+                .help("Hash the decompressed content of gzip, xz, zstd or bz2 files. The format is detected from the magic bytes unless given")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value("auto")
+                .possible_values(Decompress::NAMES)
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .value_name("template")
+                .help("Print each record with this template instead. Placeholders: {hash} {algo} {path} {basename} {size} {type} {mtime} {index}; escapes: \\t \\n \\0 \\\\; '{{' and '}}' for literal braces")
+                .takes_value(true)
+                .conflicts_with_all(&["expect", "manifest", "block-size", "watch"])
+        )
+        .arg(
+            Arg::new("zero")
+                .short('z')
+                .long("zero")
+                .help("End each --template, --quiet or --manifest record with NUL instead of a newline, so paths containing newlines stay unambiguous")
+        )
         .arg(
             Arg::new("quiet")
                 .short('q')
                 .long("quiet")
-                .help("Do not print the text/file, just the hash")
+                .global(true)
+                .help("Do not print the text/file or the progress line, just the hash")
         )
+        .subcommand(dupes::command())
+        .subcommand(bench::command())
+        .subcommand(compare::command())
+        .subcommand(blocks::command())
+        .subcommand(chunks::command())
+        .subcommand(archive::command())
+        .subcommand(kdf::command())
+        .subcommand(identify::command())
+        .subcommand(diff::command())
+        .subcommand(sign::keygen_command())
+        .subcommand(sign::sign_command())
+        .subcommand(sign::verify_command())
+        .subcommand(store::command())
+        .subcommand(fuzzy::fuzzy_command())
+        .subcommand(fuzzy::similarity_command())
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -101,7 +333,7 @@ impl<'a> Display for HexError<'a> {
     }
 }
 
-fn val(ch: char, hex: &str) -> Result<u8, HexError> {
+fn val(ch: char, hex: &str) -> Result<u8, HexError<'_>> {
     let chu8 = ch as u8;
     match ch {
         'A'..='F' => Ok(chu8 - b'A' + 10),
@@ -111,7 +343,7 @@ fn val(ch: char, hex: &str) -> Result<u8, HexError> {
     }
 }
 
-fn hex_to_byte(hex_string: &str) -> Result<u8, HexError> {
+fn hex_to_byte(hex_string: &str) -> Result<u8, HexError<'_>> {
     if hex_string.len() != 4 {
         return Err(HexError::InvalidLength { hex: hex_string });
     } else if !hex_string.starts_with("0x") && !hex_string.starts_with("0X") {
@@ -138,35 +370,89 @@ fn hex_to_byte(hex_string: &str) -> Result<u8, HexError> {
     Ok(hi * 16 + lo)
 }
 
-fn hex_to_byte_slice(hex_string: &str) -> Vec<u8> {
+fn parse_hex_bytes(hex_string: &str) -> Result<Vec<u8>, HexError<'_>> {
     hex_string
         .split(|c: char| c.is_whitespace() || c == ',')
         .map(|s| s.trim())
         .filter(|s| !s.is_empty())
-        .map(|s| match hex_to_byte(s) {
-            Ok(b) => b,
-            Err(err) => {
-                eprintln!("{}", err);
-                std::process::exit(exitcode::DATAERR);
-            }
-        })
+        .map(hex_to_byte)
         .collect()
 }
 
+fn hex_to_byte_slice(hex_string: &str) -> Vec<u8> {
+    match parse_hex_bytes(hex_string) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(exitcode::DATAERR);
+        }
+    }
+}
+
+/// Parse a byte count such as `4096`, `64K`, `1M` or `2GiB`, using binary multiples.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => return None,
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|n| *n > 0)
+}
+
+/// Exit code for a digest that does not match the expected one.
+pub const EXIT_MISMATCH: i32 = 1;
+
 fn bytes_to_hex_string(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub enum HashAlgorithm {
     MD5,
+    #[default]
     SHA256,
     BLAKE3,
 }
 
-impl Default for HashAlgorithm {
-    fn default() -> Self {
-        Self::SHA256
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HashAlgorithm::ALL
+            .iter()
+            .copied()
+            .find(|algo| algo.flag_name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown hash algorithm '{}'", s))
+    }
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 3] = [Self::MD5, Self::SHA256, Self::BLAKE3];
+
+    /// Name of the command line flag that selects this algorithm.
+    pub fn flag_name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::MD5 => "md5",
+            HashAlgorithm::SHA256 => "sha256",
+            HashAlgorithm::BLAKE3 => "blake3",
+        }
+    }
+
+    /// Length of the digest in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            HashAlgorithm::MD5 => 16,
+            HashAlgorithm::SHA256 | HashAlgorithm::BLAKE3 => 32,
+        }
     }
 }
 
@@ -177,6 +463,10 @@ pub struct OutputStyle {
     pub entry_type: &'static str,
     pub algo: HashAlgorithm,
     pub hash: String,
+    pub normalized: Vec<&'static str>,
+    /// Replaces the algorithm name on the hash line, for digests that are not a plain
+    /// `HashAlgorithm` digest.
+    pub label: Option<&'static str>,
 }
 
 impl OutputStyle {
@@ -198,24 +488,110 @@ impl OutputStyle {
         self.entry_type = "TEXT";
     }
 
+    pub fn add_tree(&mut self, path: &str) {
+        let entry_chars: Vec<_> = path.chars().take(40).collect();
+        self.len = entry_chars.len();
+        self.entry = String::from_iter(entry_chars);
+        self.entry_type = "TREE";
+    }
+
+    pub fn add_digest(&mut self, digest: &str) {
+        let entry_chars: Vec<_> = digest.chars().take(40).collect();
+        self.len = entry_chars.len();
+        self.entry = String::from_iter(entry_chars);
+        self.entry_type = "DIGEST";
+    }
+
     pub fn set_algorithm(&mut self, algorithm: HashAlgorithm) {
         self.algo = algorithm;
     }
 
+    pub fn set_label(&mut self, label: &'static str) {
+        self.label = Some(label);
+    }
+
     pub fn add_hash(&mut self, hash_str: &str) {
         self.hash = hash_str.to_string();
     }
 
-    pub fn summary(&self, action: &str) -> String {
+    pub fn add_normalization(&mut self, steps: Vec<&'static str>) {
+        self.normalized = steps;
+    }
+
+    pub fn entry_line(&self, action: &str) -> String {
         let etc = if self.len < 40 { "" } else { "..." };
+        let line = format!("[{} {}] [{}]{}", action, self.entry_type, self.entry, etc);
+        if self.normalized.is_empty() {
+            line
+        } else {
+            format!("{}\n[NORMALIZE] [{}]", line, self.normalized.join(", "))
+        }
+    }
+
+    pub fn hash_line(&self) -> String {
+        match self.label {
+            Some(label) => format!("[{} HASH] [{}]", label, self.hash),
+            None => format!("[{:?} HASH] [{}]", self.algo, self.hash),
+        }
+    }
+
+    pub fn summary(&self, action: &str) -> String {
         let surr_line = "=".repeat(80);
-        let entry_line = format!("[{} {}] [{}]{}", action, self.entry_type, self.entry, etc);
-        let hash_line = format!("[{:?} HASH] [{}]", self.algo, self.hash);
         format!(
             "{}\n{}\n{}\n{}\n",
-            surr_line, entry_line, hash_line, surr_line
+            surr_line,
+            self.entry_line(action),
+            self.hash_line(),
+            surr_line
         )
     }
+
+    pub fn verdict(&self, expected: &str, matched: bool) -> String {
+        if matched {
+            format!("{}\n{}\n", self.entry_line("MATCH"), self.hash_line())
+        } else {
+            format!(
+                "{}\n{}\n[EXPECTED HASH] [{}]\n",
+                self.entry_line("MISMATCH"),
+                self.hash_line(),
+                expected
+            )
+        }
+    }
+}
+
+enum HashState {
+    MD5(md5::Context),
+    SHA256(sha2::Sha256),
+    BLAKE3(Box<blake3::Hasher>),
+}
+
+impl HashState {
+    fn new(algo: HashAlgorithm) -> Self {
+        match algo {
+            HashAlgorithm::MD5 => Self::MD5(md5::Context::new()),
+            HashAlgorithm::SHA256 => Self::SHA256(sha2::Sha256::new()),
+            HashAlgorithm::BLAKE3 => Self::BLAKE3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, input: &[u8]) {
+        match self {
+            Self::MD5(ctx) => ctx.consume(input),
+            Self::SHA256(hasher) => hasher.update(input),
+            Self::BLAKE3(hasher) => {
+                hasher.update(input);
+            }
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::MD5(ctx) => ctx.compute().0.to_vec(),
+            Self::SHA256(hasher) => hasher.finalize().to_vec(),
+            Self::BLAKE3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -248,6 +624,21 @@ impl HashImpl {
         bytes_to_hex_string(&Self::digest(input, algo))
     }
 
+    /// Hash everything `reader` yields without holding it in memory at once.
+    pub fn digest_reader<R: Read>(reader: &mut R, algo: HashAlgorithm) -> std::io::Result<Vec<u8>> {
+        let mut state = HashState::new(algo);
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => state.update(&buf[..n]),
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(state.finalize())
+    }
+
     fn md5hash(input: &[u8]) -> Vec<u8> {
         md5::compute(input).0.to_vec()
     }
@@ -265,134 +656,389 @@ impl HashImpl {
 pub enum HashInput<'a> {
     Text(&'a str),
     File(&'a str),
+    Tree(&'a str),
 }
 
-fn get_inputs(matches: &ArgMatches) -> Vec<HashInput> {
-    let text_indices;
-    let text_values;
-    let file_indices;
-    let file_values;
-
-    match matches.indices_of("text") {
-        None => {
-            text_indices = Vec::new();
-            text_values = Vec::new()
-        }
-        Some(v) => {
-            text_indices = v.collect();
-            text_values = matches.values_of("text").unwrap().collect();
-        }
-    }
-    match matches.indices_of("file") {
-        None => {
-            file_indices = Vec::new();
-            file_values = Vec::new()
-        }
-        Some(v) => {
-            file_indices = v.collect();
-            file_values = matches.values_of("file").unwrap().collect();
-        }
+fn indexed_values<'a>(matches: &'a ArgMatches, name: &str) -> Vec<(usize, &'a str)> {
+    match matches.indices_of(name) {
+        None => Vec::new(),
+        Some(indices) => indices.zip(matches.values_of(name).unwrap()).collect(),
     }
+}
 
-    let mut i = 0;
-    let mut j = 0;
+/// Merge all inputs in command line order. `files` holds the `--file` arguments after
+/// glob expansion, see `select::file_args`.
+fn get_inputs<'a>(matches: &'a ArgMatches, files: &'a [(usize, String)]) -> Vec<HashInput<'a>> {
     let mut inputs = Vec::new();
-    while i < text_indices.len() && j < file_indices.len() {
-        if text_indices[i] < file_indices[j] {
-            inputs.push(HashInput::Text(text_values[i]));
-            i += 1;
-        } else {
-            inputs.push(HashInput::File(file_values[j]));
-            j += 1;
+    inputs.extend(indexed_values(matches, "text").into_iter().map(|(i, x)| (i, HashInput::Text(x))));
+    inputs.extend(files.iter().map(|(i, x)| (*i, HashInput::File(x))));
+    inputs.extend(indexed_values(matches, "tree").into_iter().map(|(i, x)| (i, HashInput::Tree(x))));
+    inputs.sort_by_key(|(i, _)| *i);
+    inputs.into_iter().map(|(_, input)| input).collect()
+}
+
+/// How the bytes of text and file inputs are obtained before hashing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InputOptions {
+    pub hex: bool,
+    pub decompress: Decompress,
+    pub normalize: normalize::Normalize,
+}
+
+impl InputOptions {
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        InputOptions {
+            hex: matches.is_present("hex"),
+            decompress: Decompress::from_name(matches.value_of("decompress")),
+            normalize: normalize::Normalize::from_matches(matches),
         }
     }
+}
 
-    if i < text_indices.len() {
-        inputs.extend(text_values[i..].iter().map(|x| HashInput::Text(x)));
-    } else if j < file_indices.len() {
-        inputs.extend(file_values[j..].iter().map(|x| HashInput::File(x)));
+fn read_input<R: Read>(mut reader: R, hex_input: bool) -> std::io::Result<Vec<u8>> {
+    if hex_input {
+        let mut s = String::new();
+        reader.read_to_string(&mut s)?;
+        Ok(hex_to_byte_slice(&s))
+    } else {
+        let mut v = Vec::new();
+        reader.read_to_end(&mut v)?;
+        Ok(v)
     }
+}
 
-    inputs
+fn read_file(file: &str, options: &InputOptions) -> std::io::Result<Vec<u8>> {
+    let reader = decompress::decoder(std::fs::File::open(file)?, options.decompress)?;
+    options.normalize.apply(read_input(reader, options.hex)?)
 }
 
-pub fn compute(matches: &ArgMatches, inputs: &[HashInput]) {
-    let algo = if matches.is_present("md5") {
+pub fn get_algorithm(matches: &ArgMatches) -> HashAlgorithm {
+    if matches.is_present("md5") {
         HashAlgorithm::MD5
     } else if matches.is_present("blake3") {
         HashAlgorithm::BLAKE3
     } else {
         HashAlgorithm::SHA256
-    };
+    }
+}
+
+/// Digest `bytes`, or with `--update` the framed concatenation of every input so far,
+/// `index` being the position of this input.
+fn digest_bytes(
+    hasher: &mut HashImpl,
+    bytes: &[u8],
+    algo: HashAlgorithm,
+    update_on_input: bool,
+    framing: &framing::Framing,
+    index: usize,
+) -> String {
+    if update_on_input {
+        match framing.encode(index, bytes) {
+            Ok(framed) => hasher.update(&framed),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+        let suffix = framing.suffix(algo);
+        if suffix.is_empty() {
+            hasher.hex_digest(algo)
+        } else {
+            HashImpl::hex_digest_input(&[hasher.bytes.as_slice(), &suffix].concat(), algo)
+        }
+    } else {
+        HashImpl::hex_digest_input(bytes, algo)
+    }
+}
+
+/// Print the digest of every input. Returns false if `--expect` was given and any
+/// checked digest did not match it.
+pub fn compute(matches: &ArgMatches, inputs: &[HashInput]) -> bool {
+    let algo = get_algorithm(matches);
+    let format = encoding::DigestFormat::from_name(matches.value_of("format"));
+    if let Err(err) = format.supports(algo) {
+        eprintln!("{}", err);
+        std::process::exit(exitcode::USAGE);
+    }
+    let expected = matches.value_of("expect").map(|digest| {
+        if let Some((_, tagged, _)) = encoding::decode_tagged(digest).filter(|(_, tagged, _)| *tagged != algo) {
+            eprintln!("Expected digest '{}' is a {:?} digest, but {:?} is selected", digest, tagged, algo);
+            std::process::exit(exitcode::USAGE);
+        }
+        match encoding::decode_digest(digest) {
+            Some(bytes) => bytes,
+            None => {
+                eprintln!("Invalid expected digest '{}', should be hex, base64, SRI, multihash, CID or OCI", digest);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
+    });
+    let mut all_matched = true;
 
-    let hex_input = matches.is_present("hex");
+    let options = InputOptions::from_matches(matches);
+    let hex_input = options.hex;
     let update_on_input = matches.is_present("update");
     let print_hash_only = matches.is_present("quiet");
+    let print_manifest = matches.is_present("manifest");
+
+    let normalize = options.normalize;
+    let git = git::ObjectFormat::from_matches(matches);
+    let framing = framing::Framing::from_matches(matches);
+    let template = matches.value_of("template").map(|t| match template::Template::parse(t) {
+        Ok(template) => template,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(exitcode::USAGE);
+        }
+    });
+    let terminator = if matches.is_present("zero") { "\0" } else { "\n" };
+    let mut cache = cache::HashCache::from_matches(matches);
+    // Only the raw content of a file is cached, not a decoded or accumulated form of it.
+    let cacheable = !hex_input && !update_on_input && normalize.is_empty() && options.decompress == Decompress::Off;
 
     let mut hasher = HashImpl::new();
 
-    for input in inputs.iter() {
+    let files: Vec<_> = inputs
+        .iter()
+        .filter_map(|input| match input {
+            HashInput::File(file) => Some(file),
+            _ => None,
+        })
+        .collect();
+    let total = files
+        .iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|m| m.len())
+        .sum();
+    let mut progress = Progress::new(total, files.len(), print_hash_only);
+
+    for (index, input) in inputs.iter().enumerate() {
         let mut style = OutputStyle::new();
         style.set_algorithm(algo);
-        let input_bytes = match input {
+        if let Some(format) = git {
+            style.set_label(format.label());
+        }
+        if !matches!(input, HashInput::Tree(_)) {
+            style.add_normalization(normalize.applied());
+        }
+        let mut subdirs = None;
+        let digest = match input {
             HashInput::Text(text) => {
                 if !print_hash_only {
                     style.add_text(text);
                 }
-                if hex_input {
+                let input_bytes = if hex_input {
                     hex_to_byte_slice(text)
                 } else {
-                    text.as_bytes().to_vec()
+                    match normalize.apply(text.as_bytes().to_vec()) {
+                        Ok(bytes) => bytes,
+                        Err(err) => {
+                            eprintln!("Cannot normalize text {}: {}", text, err);
+                            std::process::exit(exitcode::DATAERR);
+                        }
+                    }
+                };
+                match git {
+                    Some(format) => bytes_to_hex_string(&git::blob_id(&input_bytes, format)),
+                    None => digest_bytes(&mut hasher, &input_bytes, algo, update_on_input, &framing, index),
                 }
             }
             HashInput::File(file) => {
+                if let Some(block_size) = matches.value_of("block-size").and_then(parse_size) {
+                    blocks::print_blocks(file, algo, block_size, matches.is_present("block-root"), print_hash_only);
+                    continue;
+                }
                 if !print_hash_only {
                     style.add_file(file);
                 }
-                if hex_input {
-                    match std::fs::read_to_string(file) {
-                        Ok(s) => hex_to_byte_slice(&s),
-                        Err(err) => {
-                            eprintln!("Cannot read file {}: {}", file, err);
-                            std::process::exit(exitcode::IOERR);
+                progress.next_file();
+                // Taken before reading, so a write during hashing invalidates the entry.
+                let metadata = std::fs::metadata(file).ok().filter(|_| cacheable && cache.is_enabled());
+                let cached = metadata.as_ref().and_then(|m| cache.get(Path::new(file), m, algo));
+                let result = match (cached, git) {
+                    (Some(digest), _) => Ok(bytes_to_hex_string(&digest)),
+                    (None, Some(format)) => git::blob_id_file(Path::new(file), format).map(|d| bytes_to_hex_string(&d)),
+                    (None, None) => std::fs::File::open(file).and_then(|f| {
+                        // Progress counts the bytes on disk, which is what the total is made of.
+                        let mut reader = decompress::decoder(ProgressReader::new(f, &mut progress), options.decompress)?;
+                        if hex_input || update_on_input || !normalize.is_empty() {
+                            read_input(reader, hex_input)
+                                .and_then(|v| normalize.apply(v))
+                                .map(|v| digest_bytes(&mut hasher, &v, algo, update_on_input, &framing, index))
+                        } else {
+                            let digest = HashImpl::digest_reader(&mut reader, algo)?;
+                            if let Some(m) = &metadata {
+                                cache.insert(Path::new(file), m, algo, &digest);
+                            }
+                            Ok(bytes_to_hex_string(&digest))
                         }
+                    }),
+                };
+                progress.clear();
+                match result {
+                    Ok(digest) => digest,
+                    Err(err) => {
+                        eprintln!("Cannot read file {}: {}", file, err);
+                        std::process::exit(exitcode::IOERR);
                     }
-                } else {
-                    match std::fs::read(file) {
-                        Ok(v) => v,
-                        Err(err) => {
-                            eprintln!("Cannot read file {}: {}", file, err);
-                            std::process::exit(exitcode::IOERR);
+                }
+            }
+            HashInput::Tree(dir) if print_manifest => {
+                match manifest::tree_entries(dir, algo, &select::FileFilter::from_matches(matches), &mut cache) {
+                    Ok(entries) => {
+                        for (path, digest) in entries {
+                            print!("{}{}", manifest::format_line(&bytes_to_hex_string(&digest), &path), terminator);
                         }
                     }
+                    Err(err) => {
+                        eprintln!("Cannot hash tree {}: {}", dir, err);
+                        std::process::exit(exitcode::IOERR);
+                    }
+                }
+                continue;
+            }
+            HashInput::Tree(dir) if git.is_some() => {
+                if !print_hash_only {
+                    style.add_tree(dir);
+                }
+                let filter = select::FileFilter::from_matches(matches);
+                match git::tree_id(Path::new(dir), git.unwrap(), &filter) {
+                    Ok(id) => bytes_to_hex_string(&id),
+                    Err(err) => {
+                        eprintln!("Cannot hash tree {}: {}", dir, err);
+                        std::process::exit(exitcode::IOERR);
+                    }
+                }
+            }
+            HashInput::Tree(dir) => {
+                if !print_hash_only {
+                    style.add_tree(dir);
                 }
+                let (root, list) = tree::compute_tree(dir, algo, matches.is_present("subdirs"), &select::FileFilter::from_matches(matches), &mut cache);
+                subdirs = Some((dir, list));
+                root
             }
         };
-        if update_on_input {
-            hasher.update(&input_bytes);
-            let digest = hasher.hex_digest(algo);
-            if print_hash_only {
-                println!("{}", digest);
-            } else {
+        let digest = match format {
+            encoding::DigestFormat::Hex => digest,
+            _ => format.render(&encoding::decode_plain_hex(&digest).unwrap_or_default(), algo),
+        };
+        if let Some(expected) = &expected {
+            // With --update only the finalized digest is checked.
+            if update_on_input && index + 1 < inputs.len() {
+                continue;
+            }
+            let actual = encoding::decode_digest(&digest).unwrap_or_default();
+            let matched = encoding::constant_time_eq(&actual, expected);
+            all_matched &= matched;
+            if !print_hash_only {
                 style.add_hash(&digest);
-                println!("{}", style.summary("UPDATE"));
+                println!("{}", style.verdict(&format.render(expected, algo), matched));
             }
+        } else if let Some(template) = &template {
+            let algo_name = match git {
+                Some(format) => format.label().to_lowercase().replace(' ', "-"),
+                None => algo.flag_name().to_string(),
+            };
+            let record = template::Record {
+                hash: &digest,
+                algo: &algo_name,
+                input,
+                index,
+            };
+            print!("{}{}", template.render(&record), terminator);
+        } else if let (true, HashInput::File(file)) = (print_manifest, input) {
+            print!("{}{}", manifest::format_line(&digest, manifest::normalize_path(file)), terminator);
         } else {
-            let digest = HashImpl::hex_digest_input(&input_bytes, algo);
+            let action = if update_on_input { "UPDATE" } else { "COMPUTE" };
             if print_hash_only {
-                println!("{}", digest);
+                print!("{}{}", digest, terminator);
             } else {
                 style.add_hash(&digest);
-                println!("{}", style.summary("COMPUTE"));
+                println!("{}", style.summary(action));
             }
         }
+        if let Some((dir, list)) = subdirs {
+            tree::print_subdirs(dir, list);
+        }
+    }
+    if let Err(err) = cache.save() {
+        eprintln!("Cannot write cache: {}", err);
     }
+    all_matched
 }
 
 fn main() {
     let app = build_app();
     let matches = app.get_matches();
-    let inputs = get_inputs(&matches);
-    compute(&matches, &inputs);
+    match matches.subcommand() {
+        Some(("dupes", sub_matches)) => dupes::run(sub_matches),
+        Some(("bench", sub_matches)) => bench::run(sub_matches),
+        Some(("compare", sub_matches)) => {
+            if !compare::run(sub_matches) {
+                std::process::exit(EXIT_MISMATCH);
+            }
+        }
+        Some(("chunks", sub_matches)) => chunks::run(sub_matches),
+        Some(("archive", sub_matches)) => archive::run(sub_matches),
+        Some(("kdf", sub_matches)) => kdf::run(sub_matches),
+        Some(("diff", sub_matches)) => {
+            if !diff::run(sub_matches) {
+                std::process::exit(EXIT_MISMATCH);
+            }
+        }
+        Some(("keygen", sub_matches)) => sign::run_keygen(sub_matches),
+        Some(("sign", sub_matches)) => sign::run_sign(sub_matches),
+        Some(("verify-sig", sub_matches)) => {
+            if !sign::run_verify(sub_matches) {
+                std::process::exit(EXIT_MISMATCH);
+            }
+        }
+        Some(("store", sub_matches)) => {
+            if !store::run(sub_matches) {
+                std::process::exit(EXIT_MISMATCH);
+            }
+        }
+        Some(("fuzzy", sub_matches)) => fuzzy::run_fuzzy(sub_matches),
+        Some(("similarity", sub_matches)) => {
+            if !fuzzy::run_similarity(sub_matches) {
+                std::process::exit(EXIT_MISMATCH);
+            }
+        }
+        Some(("identify", sub_matches)) => {
+            if !identify::run(sub_matches) {
+                std::process::exit(EXIT_MISMATCH);
+            }
+        }
+        Some(("block-diff", sub_matches)) => {
+            if !blocks::run(sub_matches) {
+                std::process::exit(EXIT_MISMATCH);
+            }
+        }
+        _ if matches.is_present("interactive") => interactive::run(&matches),
+        _ if matches.is_present("self-test") => {
+            if selftest::run(matches.is_present("quiet")) > 0 {
+                std::process::exit(exitcode::SOFTWARE);
+            }
+        }
+        _ => {
+            let files = select::file_args(&matches, &select::FileFilter::from_matches(&matches));
+            let inputs = get_inputs(&matches, &files);
+            if !compute(&matches, &inputs) {
+                std::process::exit(EXIT_MISMATCH);
+            }
+            if matches.is_present("watch") {
+                let debounce = matches.value_of_t("debounce").unwrap_or_else(|e| e.exit());
+                watch::watch(
+                    &inputs,
+                    get_algorithm(&matches),
+                    &InputOptions::from_matches(&matches),
+                    &select::FileFilter::from_matches(&matches),
+                    matches.is_present("quiet"),
+                    std::time::Duration::from_millis(debounce),
+                );
+            }
+        }
+    }
     std::process::exit(exitcode::OK);
 }
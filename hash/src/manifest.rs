@@ -0,0 +1,74 @@
+//! Checksum manifests in the format of `sha256sum` and friends: one
+//! `<hex digest>  <path>` line per file. Paths containing a backslash or a newline are
+//! escaped and the line is prefixed with a backslash, as coreutils does.
+
+use crate::cache::HashCache;
+use crate::select::FileFilter;
+use crate::HashAlgorithm;
+use std::path::Path;
+
+pub fn escape_path(path: &str) -> (bool, String) {
+    if path.contains(['\\', '\n']) {
+        (true, path.replace('\\', "\\\\").replace('\n', "\\n"))
+    } else {
+        (false, path.to_string())
+    }
+}
+
+pub fn format_line(digest: &str, path: &str) -> String {
+    let (escaped, path) = escape_path(path);
+    format!("{}{}  {}", if escaped { "\\" } else { "" }, digest, path)
+}
+
+/// Path of a manifest entry as written by tools that walk a directory: no leading `./`.
+pub fn normalize_path(path: &str) -> &str {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path
+}
+
+/// Digest every regular file below `dir` that passes `filter`, keyed by its path
+/// relative to `dir` and sorted by it. Symbolic links are not followed, matching what
+/// an archive member listing contains.
+pub fn tree_entries(
+    dir: &str,
+    algo: HashAlgorithm,
+    filter: &FileFilter,
+    cache: &mut HashCache,
+) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    for path in filter.walk(Path::new(dir))? {
+        let digest = cache.file_digest(&path, &std::fs::symlink_metadata(&path)?, algo)?;
+        let rel = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy();
+        entries.push((normalize_path(&rel).to_string(), digest));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_like_coreutils() {
+        assert_eq!(format_line("ab", "plain name"), "ab  plain name");
+        assert_eq!(format_line("ab", "a\\b\nc"), "\\ab  a\\\\b\\nc");
+        assert_eq!(crate::cache::unescape_path(&escape_path("a\\b\nc").1), "a\\b\nc");
+    }
+
+    #[test]
+    fn tree_entries_are_relative_to_the_tree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a"), "").unwrap();
+        std::fs::write(dir.join("sub/b"), "").unwrap();
+        let mut cache = HashCache::disabled();
+        let entries = tree_entries(&dir.to_string_lossy(), HashAlgorithm::SHA256, &FileFilter::default(), &mut cache).unwrap();
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a", "sub/b"]);
+    }
+}
@@ -0,0 +1,207 @@
+//! Text normalisation applied to text and file inputs before hashing, so that the same
+//! text gives the same digest whatever line endings or Unicode composition it was
+//! saved with. The steps always run in the order of `Normalize::NAMES`.
+
+use clap::ArgMatches;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnicodeForm {
+    Nfc,
+    Nfkc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FinalNewline {
+    Strip,
+    Append,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Normalize {
+    pub crlf: bool,
+    pub unicode: Option<UnicodeForm>,
+    pub trim: bool,
+    pub final_newline: Option<FinalNewline>,
+}
+
+impl Normalize {
+    pub const NAMES: [&'static str; 6] = ["crlf", "nfc", "nfkc", "trim", "strip-newline", "final-newline"];
+
+    /// Build the normalisation from `--normalize`, exiting on contradictory steps.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let mut normalize = Normalize::default();
+        for name in matches.values_of("normalize").into_iter().flatten() {
+            let conflict = match name {
+                "crlf" => {
+                    normalize.crlf = true;
+                    false
+                }
+                "trim" => {
+                    normalize.trim = true;
+                    false
+                }
+                "nfc" => normalize.unicode.replace(UnicodeForm::Nfc) == Some(UnicodeForm::Nfkc),
+                "nfkc" => normalize.unicode.replace(UnicodeForm::Nfkc) == Some(UnicodeForm::Nfc),
+                "strip-newline" => {
+                    normalize.final_newline.replace(FinalNewline::Strip) == Some(FinalNewline::Append)
+                }
+                _ => normalize.final_newline.replace(FinalNewline::Append) == Some(FinalNewline::Strip),
+            };
+            if conflict {
+                eprintln!("--normalize: '{}' contradicts an earlier step", name);
+                std::process::exit(exitcode::USAGE);
+            }
+        }
+        normalize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Normalize::default()
+    }
+
+    /// Names of the steps that are applied, in the order they run.
+    pub fn applied(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.crlf {
+            names.push("crlf");
+        }
+        match self.unicode {
+            Some(UnicodeForm::Nfc) => names.push("nfc"),
+            Some(UnicodeForm::Nfkc) => names.push("nfkc"),
+            None => {}
+        }
+        if self.trim {
+            names.push("trim");
+        }
+        match self.final_newline {
+            Some(FinalNewline::Strip) => names.push("strip-newline"),
+            Some(FinalNewline::Append) => names.push("final-newline"),
+            None => {}
+        }
+        names
+    }
+
+    /// Normalise `bytes`. Only the Unicode forms need valid UTF-8, the other steps
+    /// work on any bytes.
+    pub fn apply(&self, bytes: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        if self.is_empty() {
+            return Ok(bytes);
+        }
+        let mut bytes = bytes;
+        if self.crlf {
+            bytes = replace_crlf(&bytes);
+        }
+        if let Some(form) = self.unicode {
+            let text = String::from_utf8(bytes).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Unicode normalisation needs UTF-8 input",
+                )
+            })?;
+            bytes = match form {
+                UnicodeForm::Nfc => text.nfc().collect::<String>(),
+                UnicodeForm::Nfkc => text.nfkc().collect::<String>(),
+            }
+            .into_bytes();
+        }
+        if self.trim {
+            bytes = trim_lines(&bytes);
+        }
+        match self.final_newline {
+            // Only the line ending of the last line, so blank lines at the end remain.
+            Some(FinalNewline::Strip) if bytes.last() == Some(&b'\n') => {
+                bytes.pop();
+                if bytes.last() == Some(&b'\r') {
+                    bytes.pop();
+                }
+            }
+            Some(FinalNewline::Append) if !bytes.is_empty() && bytes.last() != Some(&b'\n') => {
+                bytes.push(b'\n');
+            }
+            _ => {}
+        }
+        Ok(bytes)
+    }
+}
+
+fn replace_crlf(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter().peekable();
+    while let Some(&b) = iter.next() {
+        if b == b'\r' && iter.peek() == Some(&&b'\n') {
+            continue;
+        }
+        out.push(b);
+    }
+    out
+}
+
+/// Remove spaces and tabs before every line ending and at the end of the input.
+fn trim_lines(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    for (i, line) in bytes.split(|b| *b == b'\n').enumerate() {
+        if i > 0 {
+            out.push(b'\n');
+        }
+        let (line, cr) = match line.strip_suffix(b"\r") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        let end = line.iter().rposition(|b| *b != b' ' && *b != b'\t').map_or(0, |p| p + 1);
+        out.extend_from_slice(&line[..end]);
+        if cr {
+            out.push(b'\r');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(normalize: Normalize, input: &str) -> String {
+        String::from_utf8(normalize.apply(input.as_bytes().to_vec()).unwrap()).unwrap()
+    }
+
+    fn newline(final_newline: FinalNewline) -> Normalize {
+        Normalize { final_newline: Some(final_newline), ..Normalize::default() }
+    }
+
+    #[test]
+    fn strip_newline_removes_one_line_ending() {
+        let strip = newline(FinalNewline::Strip);
+        assert_eq!(apply(strip, "a\n"), "a");
+        assert_eq!(apply(strip, "a\r\n"), "a");
+        assert_eq!(apply(strip, "a\n\n"), "a\n");
+        assert_eq!(apply(strip, "a\r\n\r\n"), "a\r\n");
+        assert_eq!(apply(strip, "a"), "a");
+    }
+
+    #[test]
+    fn final_newline_appends_only_when_missing() {
+        let append = newline(FinalNewline::Append);
+        assert_eq!(apply(append, "a"), "a\n");
+        assert_eq!(apply(append, "a\n"), "a\n");
+        assert_eq!(apply(append, ""), "");
+    }
+
+    #[test]
+    fn crlf_and_trim_work_on_any_bytes() {
+        let both = Normalize { crlf: true, trim: true, ..Normalize::default() };
+        assert_eq!(both.apply(b"\xff \t\r\n\r\xfe\t".to_vec()).unwrap(), b"\xff\n\r\xfe");
+        let trim = Normalize { trim: true, ..Normalize::default() };
+        assert_eq!(apply(trim, "a \r\nb\t"), "a\r\nb");
+    }
+
+    #[test]
+    fn steps_run_in_order() {
+        let all = Normalize { crlf: true, unicode: Some(UnicodeForm::Nfc), trim: true, final_newline: Some(FinalNewline::Strip) };
+        assert_eq!(apply(all, "e\u{301} \r\nb\t\r\n"), "\u{e9}\nb");
+        assert_eq!(all.applied(), ["crlf", "nfc", "trim", "strip-newline"]);
+        let nfkc = Normalize { unicode: Some(UnicodeForm::Nfkc), ..Normalize::default() };
+        assert_eq!(apply(nfkc, "\u{fb01}"), "fi");
+        assert!(nfkc.apply(vec![0xff]).is_err());
+    }
+}
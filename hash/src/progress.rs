@@ -0,0 +1,156 @@
+use std::io::{IsTerminal, Read, Write};
+use std::time::{Duration, Instant};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Progress line drawn on stderr while large inputs are hashed. It stays silent unless
+/// stderr is a terminal, so redirected output and pipes never see it.
+pub struct Progress {
+    enabled: bool,
+    total: u64,
+    done: u64,
+    files: usize,
+    current: usize,
+    start: Instant,
+    last_draw: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(total: u64, files: usize, quiet: bool) -> Self {
+        Progress {
+            enabled: !quiet && std::io::stderr().is_terminal(),
+            total,
+            done: 0,
+            files,
+            current: 0,
+            start: Instant::now(),
+            last_draw: None,
+        }
+    }
+
+    pub fn next_file(&mut self) {
+        self.current += 1;
+    }
+
+    pub fn add(&mut self, n: u64) {
+        self.done += n;
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        if self.last_draw.is_none_or(|t| now.duration_since(t) >= REDRAW_INTERVAL) {
+            self.last_draw = Some(now);
+            self.draw(now);
+        }
+    }
+
+    fn draw(&self, now: Instant) {
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let rate = if elapsed > 0.0 { self.done as f64 / elapsed } else { 0.0 };
+        let percent = if self.total > 0 {
+            (self.done as f64 * 100.0 / self.total as f64).min(100.0)
+        } else {
+            100.0
+        };
+        let eta = if rate > 0.0 {
+            format_duration(self.total.saturating_sub(self.done) as f64 / rate)
+        } else {
+            "--:--".to_string()
+        };
+        let batch = if self.files > 1 {
+            format!("[{}/{}] ", self.current, self.files)
+        } else {
+            String::new()
+        };
+        let mut stderr = std::io::stderr();
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K{}{} / {} ({:.1}%) {}/s ETA {}",
+            batch,
+            format_bytes(self.done),
+            format_bytes(self.total),
+            percent,
+            format_bytes(rate as u64),
+            eta
+        );
+        let _ = stderr.flush();
+    }
+
+    /// Erase the progress line so a record can be printed in its place.
+    pub fn clear(&mut self) {
+        if self.enabled && self.last_draw.take().is_some() {
+            let mut stderr = std::io::stderr();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+fn format_bytes(n: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", n, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a mut Progress,
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    pub fn new(inner: R, progress: &'a mut Progress) -> Self {
+        ProgressReader { inner, progress }
+    }
+}
+
+impl<'a, R: Read> Read for ProgressReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.add(n as u64);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_sizes_and_durations() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 << 30), "5.0 GiB");
+        assert_eq!(format_bytes(u64::MAX), "16777216.0 TiB");
+        assert_eq!(format_duration(0.4), "00:00");
+        assert_eq!(format_duration(59.6), "01:00");
+        assert_eq!(format_duration(3725.0), "1:02:05");
+    }
+
+    #[test]
+    fn reader_counts_bytes() {
+        let mut progress = Progress::new(11, 1, true);
+        let mut out = Vec::new();
+        ProgressReader::new(&b"hello world"[..], &mut progress).read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello world");
+        assert_eq!(progress.done, 11);
+    }
+}
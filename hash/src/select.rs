@@ -0,0 +1,243 @@
+//! Selection of file inputs: glob expansion, `--exclude` patterns and ignore files.
+
+use crate::manifest::normalize_path;
+use clap::ArgMatches;
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use std::path::{Component, Path, PathBuf};
+
+/// Version control metadata directories, skipped together with ignored files.
+const VCS_DIRS: [&str; 4] = [".git", ".hg", ".svn", "_darcs"];
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '{'])
+}
+
+fn glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+/// The default filter keeps every file.
+#[derive(Clone, Debug, Default)]
+pub struct FileFilter {
+    exclude: GlobSet,
+    respect_ignore: bool,
+}
+
+impl FileFilter {
+    /// Build the filter from `--exclude` and `--gitignore`, exiting on an invalid pattern.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in matches.values_of("exclude").into_iter().flatten() {
+            let pattern = normalize_path(pattern).trim_end_matches('/');
+            // Like .gitignore, a pattern without a slash matches a name at any depth,
+            // and excluding a directory excludes everything below it.
+            let anchored = if pattern.contains('/') {
+                pattern.to_string()
+            } else {
+                format!("**/{}", pattern)
+            };
+            for p in [anchored.clone(), format!("{}/**", anchored)] {
+                match glob(&p) {
+                    Ok(g) => {
+                        builder.add(g);
+                    }
+                    Err(err) => {
+                        eprintln!("Invalid exclude pattern '{}': {}", pattern, err);
+                        std::process::exit(exitcode::USAGE);
+                    }
+                }
+            }
+        }
+        FileFilter {
+            exclude: builder.build().unwrap_or_else(|_| GlobSet::empty()),
+            respect_ignore: matches.is_present("gitignore"),
+        }
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.exclude.is_match(normalize_path(&path.to_string_lossy()))
+    }
+
+    /// Every regular file below `base` that passes the filter, sorted by path.
+    pub fn walk(&self, base: &Path) -> std::io::Result<Vec<PathBuf>> {
+        self.walk_matching(base, |t| t.is_file())
+    }
+
+    /// Like `walk`, but also lists symbolic links, which are not followed.
+    pub fn walk_with_links(&self, base: &Path) -> std::io::Result<Vec<PathBuf>> {
+        self.walk_matching(base, |t| t.is_file() || t.is_symlink())
+    }
+
+    /// Every entry below `base` that passes the filter, directories and special files
+    /// included. Links are listed but not followed.
+    pub fn walk_all(&self, base: &Path) -> std::io::Result<Vec<PathBuf>> {
+        self.walk_matching(base, |_| true)
+    }
+
+    fn walk_matching(&self, base: &Path, keep: fn(&std::fs::FileType) -> bool) -> std::io::Result<Vec<PathBuf>> {
+        let mut builder = WalkBuilder::new(base);
+        builder
+            .standard_filters(false)
+            .follow_links(false)
+            .sort_by_file_name(|a, b| a.cmp(b));
+        if self.respect_ignore {
+            builder
+                .git_ignore(true)
+                .git_exclude(true)
+                .ignore(true)
+                .parents(true)
+                .require_git(false)
+                .filter_entry(|e| !VCS_DIRS.iter().any(|d| e.file_name() == *d));
+        }
+        let mut files = Vec::new();
+        for entry in builder.build() {
+            let entry = entry.map_err(|e| std::io::Error::other(e.to_string()))?;
+            if entry.file_type().is_some_and(|t| keep(&t)) && !self.is_excluded(entry.path()) {
+                files.push(entry.into_path());
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Expand a `--file` argument. Literal paths are kept unless excluded; glob
+    /// patterns such as `src/**/*.rs` are matched against the files below their
+    /// longest literal prefix.
+    pub fn expand(&self, pattern: &str) -> std::io::Result<Vec<String>> {
+        if !is_glob(pattern) || Path::new(pattern).exists() {
+            return Ok(if self.is_excluded(Path::new(pattern)) {
+                Vec::new()
+            } else {
+                vec![pattern.to_string()]
+            });
+        }
+        let normalized = normalize_path(pattern);
+        let matcher: GlobMatcher = glob(normalized)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+            .compile_matcher();
+        // Keep the root of absolute patterns, which splitting on '/' would drop.
+        let base: PathBuf = Path::new(normalized)
+            .components()
+            .take_while(|component| match component {
+                Component::Normal(name) => !is_glob(&name.to_string_lossy()),
+                _ => true,
+            })
+            .collect();
+        let base = if base.as_os_str().is_empty() { PathBuf::from(".") } else { base };
+        Ok(self
+            .walk(&base)?
+            .into_iter()
+            .map(|p| normalize_path(&p.to_string_lossy()).to_string())
+            .filter(|p| matcher.is_match(p))
+            .collect())
+    }
+}
+
+/// All `--file` arguments expanded in command line order, each paired with the index
+/// of the argument it came from so it can be merged with the other inputs.
+pub fn file_args(matches: &ArgMatches, filter: &FileFilter) -> Vec<(usize, String)> {
+    let (indices, values) = match (matches.indices_of("file"), matches.values_of("file")) {
+        (Some(i), Some(v)) => (i, v),
+        _ => return Vec::new(),
+    };
+    let mut files = Vec::new();
+    for (index, pattern) in indices.zip(values) {
+        match filter.expand(pattern) {
+            Ok(paths) if paths.is_empty() && is_glob(pattern) => {
+                eprintln!("No files match pattern {}", pattern);
+                std::process::exit(exitcode::NOINPUT);
+            }
+            Ok(paths) => files.extend(paths.into_iter().map(|p| (index, p))),
+            Err(err) => {
+                eprintln!("Cannot expand pattern {}: {}", pattern, err);
+                std::process::exit(exitcode::IOERR);
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, Command};
+
+    fn sources() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["src/a.rs", "src/sub/b.rs", "src/c.txt", "d.rs"] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+        dir
+    }
+
+    fn filter(args: &[&str]) -> FileFilter {
+        let matches = Command::new("hash")
+            .arg(Arg::new("exclude").long("exclude").takes_value(true).multiple_occurrences(true))
+            .arg(Arg::new("gitignore").long("gitignore"))
+            .get_matches_from(std::iter::once("hash").chain(args.iter().copied()));
+        FileFilter::from_matches(&matches)
+    }
+
+    fn relative_walk(filter: &FileFilter, dir: &Path) -> Vec<PathBuf> {
+        let files = filter.walk(dir).unwrap();
+        files.iter().map(|p| p.strip_prefix(dir).unwrap().to_path_buf()).collect()
+    }
+
+    #[test]
+    fn expands_absolute_glob() {
+        let dir = sources();
+        let root = dir.path().to_string_lossy();
+        let found = filter(&[]).expand(&format!("{}/src/**/*.rs", root)).unwrap();
+        assert_eq!(found, vec![format!("{}/src/a.rs", root), format!("{}/src/sub/b.rs", root)]);
+    }
+
+    #[test]
+    fn expands_relative_glob() {
+        let dir = sources();
+        let dir = dir.path();
+        let relative = dir.strip_prefix(std::env::current_dir().unwrap()).map(Path::to_path_buf);
+        // The temporary directory is rarely below the working directory, so walk up to it.
+        let relative = relative.unwrap_or_else(|_| {
+            let cwd = std::env::current_dir().unwrap();
+            let mut up = PathBuf::new();
+            for _ in cwd.components().skip(1) {
+                up.push("..");
+            }
+            up.join(dir.strip_prefix("/").unwrap())
+        });
+        let root = relative.to_string_lossy();
+        let found = filter(&[]).expand(&format!("{}/src/*.rs", root)).unwrap();
+        assert_eq!(found, vec![format!("{}/src/a.rs", root)]);
+    }
+
+    #[test]
+    fn literal_paths_are_kept_unless_excluded() {
+        let filter = filter(&["--exclude", "*.txt"]);
+        assert_eq!(filter.expand("missing.rs").unwrap(), vec!["missing.rs"]);
+        assert!(filter.expand("notes.txt").unwrap().is_empty());
+    }
+
+    #[test]
+    fn excludes_names_at_any_depth_and_whole_directories() {
+        let dir = sources();
+        let by_name = filter(&["--exclude", "*.rs"]);
+        assert_eq!(relative_walk(&by_name, dir.path()), [Path::new("src/c.txt")]);
+        let by_dir = filter(&["--exclude", "sub", "--exclude", "src/*.txt"]);
+        assert!(by_dir.is_excluded(Path::new("src/sub/b.rs")));
+        assert!(!by_dir.is_excluded(Path::new("other/c.txt")));
+    }
+
+    #[test]
+    fn gitignore_is_opt_in() {
+        let dir = sources();
+        std::fs::write(dir.path().join(".gitignore"), "*.txt\n").unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/HEAD"), "").unwrap();
+        assert_eq!(relative_walk(&filter(&[]), dir.path()).len(), 6);
+        let ignoring = relative_walk(&filter(&["--gitignore"]), dir.path());
+        assert_eq!(ignoring, [Path::new(".gitignore"), Path::new("d.rs"), Path::new("src/a.rs"), Path::new("src/sub/b.rs")]);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::fuzzy::FuzzyAlgorithm;
+use crate::git::{self, ObjectFormat};
+use crate::HashAlgorithm::{BLAKE3, MD5, SHA256};
+use crate::{bytes_to_hex_string, HashAlgorithm, HashImpl};
+use VectorInput::{Blake3Pattern, Bytes, Repeat, Text};
+
+#[derive(Clone, Copy, Debug)]
+enum VectorInput {
+    Text(&'static str),
+    Bytes(&'static [u8]),
+    Repeat(u8, usize),
+    /// The BLAKE3 test vector input: `len` bytes counting 0, 1, ..., 250, 0, 1, ...
+    Blake3Pattern(usize),
+}
+
+impl VectorInput {
+    fn bytes(&self) -> Vec<u8> {
+        match *self {
+            VectorInput::Text(text) => text.as_bytes().to_vec(),
+            VectorInput::Bytes(bytes) => bytes.to_vec(),
+            VectorInput::Repeat(b, n) => vec![b; n],
+            VectorInput::Blake3Pattern(len) => (0..len).map(|i| (i % 251) as u8).collect(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            VectorInput::Text(text) if text.len() > 40 => format!("\"{}...\"", &text[..40]),
+            VectorInput::Text(text) => format!("\"{}\"", text.escape_debug()),
+            VectorInput::Bytes(bytes) => format!("0x{}", bytes_to_hex_string(bytes)),
+            VectorInput::Repeat(b, n) => format!("{} x 0x{:02x}", n, b),
+            VectorInput::Blake3Pattern(len) => format!("{} pattern bytes", len),
+        }
+    }
+}
+
+/// What a vector checks: a digest, a git object id or a similarity digest.
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    Hash(HashAlgorithm),
+    Git(ObjectFormat),
+    Fuzzy(FuzzyAlgorithm),
+}
+
+impl Target {
+    fn name(&self) -> String {
+        match self {
+            Target::Hash(algo) => format!("{:?}", algo),
+            Target::Git(format) => format.label().to_string(),
+            Target::Fuzzy(algo) => algo.label().to_string(),
+        }
+    }
+}
+
+struct KnownAnswer {
+    target: Target,
+    source: &'static str,
+    input: VectorInput,
+    expected: &'static str,
+}
+
+const fn ka(algo: HashAlgorithm, source: &'static str, input: VectorInput, expected: &'static str) -> KnownAnswer {
+    KnownAnswer { target: Target::Hash(algo), source, input, expected }
+}
+
+const fn git(format: ObjectFormat, input: VectorInput, expected: &'static str) -> KnownAnswer {
+    KnownAnswer { target: Target::Git(format), source: "git hash-object", input, expected }
+}
+
+const fn fuzzy(algo: FuzzyAlgorithm, source: &'static str, input: VectorInput, expected: &'static str) -> KnownAnswer {
+    KnownAnswer { target: Target::Fuzzy(algo), source, input, expected }
+}
+
+const CTPH_A: &str = "Also called fuzzy hashes, Ctph can match inputs that have homologies.";
+const CTPH_B: &str = "Also called fuzzy hashes, CTPH can match inputs that have homologies.";
+
+const VECTORS: &[KnownAnswer] = &[
+    ka(MD5, "RFC 1321", Text(""), "d41d8cd98f00b204e9800998ecf8427e"),
+    ka(MD5, "RFC 1321", Text("a"), "0cc175b9c0f1b6a831c399e269772661"),
+    ka(MD5, "RFC 1321", Text("abc"), "900150983cd24fb0d6963f7d28e17f72"),
+    ka(MD5, "RFC 1321", Text("message digest"), "f96b697d7cb7938d525a2f31aaf161d0"),
+    ka(MD5, "RFC 1321", Text("abcdefghijklmnopqrstuvwxyz"), "c3fcd3d76192e4007dfb496cca67e13b"),
+    ka(
+        MD5,
+        "RFC 1321",
+        Text("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"),
+        "d174ab98d277d9f5a5611c2c9f419d9f",
+    ),
+    ka(
+        MD5,
+        "RFC 1321",
+        Text("12345678901234567890123456789012345678901234567890123456789012345678901234567890"),
+        "57edf4a22be3c955ac49da2e2107b67a",
+    ),
+    ka(SHA256, "FIPS 180-2", Text("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+    ka(
+        SHA256,
+        "FIPS 180-2",
+        Text("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+    ),
+    ka(
+        SHA256,
+        "FIPS 180-2",
+        Repeat(b'a', 1_000_000),
+        "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+    ),
+    ka(
+        SHA256,
+        "NIST CAVP SHA256ShortMsg",
+        Text(""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    ),
+    ka(
+        SHA256,
+        "NIST CAVP SHA256ShortMsg",
+        Bytes(&[0xd3]),
+        "28969cdfa74a12c82f3bad960b0b000aca2ac329deea5c2328ebc6f2ba9802c1",
+    ),
+    ka(
+        SHA256,
+        "NIST CAVP SHA256ShortMsg",
+        Bytes(&[0x11, 0xaf]),
+        "5ca7133fa735326081558ac312c620eeca9970d1e70a4b95533d956f072d1f98",
+    ),
+    ka(
+        SHA256,
+        "NIST CAVP SHA256ShortMsg",
+        Bytes(&[0xb4, 0x19, 0x0e]),
+        "dff2e73091f6c05e528896c4c831b9448653dc2ff043528f6769437bc7b975c2",
+    ),
+    ka(
+        SHA256,
+        "NIST CAVP SHA256ShortMsg",
+        Bytes(&[0x74, 0xba, 0x25, 0x21]),
+        "b16aa56be3880d18cd41e68384cf1ec8c17680c45a02b1575dc1518923ae8b0e",
+    ),
+    ka(
+        BLAKE3,
+        "BLAKE3 test_vectors.json",
+        Blake3Pattern(0),
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+    ),
+    ka(
+        BLAKE3,
+        "BLAKE3 test_vectors.json",
+        Blake3Pattern(1),
+        "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+    ),
+    ka(
+        BLAKE3,
+        "BLAKE3 test_vectors.json",
+        Blake3Pattern(1023),
+        "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11",
+    ),
+    ka(
+        BLAKE3,
+        "BLAKE3 test_vectors.json",
+        Blake3Pattern(1024),
+        "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7",
+    ),
+    ka(
+        BLAKE3,
+        "BLAKE3 test_vectors.json",
+        Blake3Pattern(1025),
+        "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444",
+    ),
+    ka(
+        BLAKE3,
+        "BLAKE3 test_vectors.json",
+        Blake3Pattern(8193),
+        "bab6c09cb8ce8cf459261398d2e7aef35700bf488116ceb94a36d0f5f1b7bc3b",
+    ),
+    ka(
+        BLAKE3,
+        "BLAKE3 test_vectors.json",
+        Blake3Pattern(102400),
+        "bc3e3d41a1146b069abffad3c0d44860cf664390afce4d9661f7902e7943e085",
+    ),
+    git(ObjectFormat::Sha1, Text(""), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"),
+    git(ObjectFormat::Sha1, Text("hello\n"), "ce013625030ba8dba906f756967f9e9ca394464a"),
+    git(ObjectFormat::Sha256, Text(""), "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813"),
+    git(ObjectFormat::Sha256, Text("hello\n"), "2cf8d83d9ee29543b34a87727421fdecb7e3f3a183d337639025de576db9ebb4"),
+    fuzzy(FuzzyAlgorithm::Ssdeep, "python-ssdeep docs", Text(""), "3::"),
+    fuzzy(FuzzyAlgorithm::Ssdeep, "python-ssdeep docs", Text(CTPH_A), "3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C"),
+    fuzzy(FuzzyAlgorithm::Ssdeep, "python-ssdeep docs", Text(CTPH_B), "3:AXGBicFlIHBGcL6wCrFQEv:AXGH6xLsr2C"),
+];
+
+/// Expected `similarity` scores of two digests.
+const SCORES: &[(FuzzyAlgorithm, &str, &str, &str, u32)] = &[(
+    FuzzyAlgorithm::Ssdeep,
+    "python-ssdeep docs",
+    "3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C",
+    "3:AXGBicFlIHBGcL6wCrFQEv:AXGH6xLsr2C",
+    22,
+)];
+
+/// Digest `input` through each path the CLI uses: one-shot for `--text`, accumulated
+/// for `--update`, and streamed for `--file`.
+fn cli_digests(input: &[u8], algo: HashAlgorithm) -> [(&'static str, String); 3] {
+    let mut hasher = HashImpl::new();
+    hasher.update(input);
+    let streamed = HashImpl::digest_reader(&mut &input[..], algo)
+        .map(|d| bytes_to_hex_string(&d))
+        .unwrap_or_default();
+    [
+        ("one-shot", HashImpl::hex_digest_input(input, algo)),
+        ("update", hasher.hex_digest(algo)),
+        ("streamed", streamed),
+    ]
+}
+
+fn digests(input: &[u8], target: Target) -> Vec<(&'static str, String)> {
+    match target {
+        Target::Hash(algo) => cli_digests(input, algo).to_vec(),
+        Target::Git(format) => vec![("blob", bytes_to_hex_string(&git::blob_id(input, format)))],
+        Target::Fuzzy(algo) => {
+            let digest = algo.digest_reader(&mut &input[..]).map_err(|e| e.to_string()).and_then(|d| d);
+            vec![("streamed", digest.unwrap_or_else(|e| e))]
+        }
+    }
+}
+
+/// Run every known-answer vector and return the number of mismatches.
+pub fn run(print_failures_only: bool) -> usize {
+    let mut failures = 0;
+    for vector in VECTORS {
+        let input = vector.input.bytes();
+        let mismatches: Vec<_> = digests(&input, vector.target)
+            .into_iter()
+            .filter(|(_, digest)| digest != vector.expected)
+            .collect();
+        if mismatches.is_empty() {
+            if !print_failures_only {
+                println!("[PASS] [{}] [{}] [{}]", vector.target.name(), vector.source, vector.input.describe());
+            }
+            continue;
+        }
+        failures += 1;
+        println!("[FAIL] [{}] [{}] [{}]", vector.target.name(), vector.source, vector.input.describe());
+        println!("    expected {}", vector.expected);
+        for (path, digest) in mismatches {
+            println!("    {:<8} {}", path, digest);
+        }
+    }
+    for &(algo, source, a, b, expected) in SCORES {
+        let score = algo.compare(a, b);
+        if score == Ok(expected) {
+            if !print_failures_only {
+                println!("[PASS] [{} SIMILARITY] [{}] [{} {}]", algo.label(), source, a, b);
+            }
+            continue;
+        }
+        failures += 1;
+        println!("[FAIL] [{} SIMILARITY] [{}] [{} {}]", algo.label(), source, a, b);
+        println!("    expected {}", expected);
+        println!("    got      {:?}", score);
+    }
+    let total = VECTORS.len() + SCORES.len();
+    if !print_failures_only || failures > 0 {
+        println!("{} passed, {} failed", total - failures, failures);
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_vector_passes() {
+        assert_eq!(run(true), 0);
+    }
+
+    #[test]
+    fn cli_paths_agree() {
+        for algo in HashAlgorithm::ALL {
+            let [one_shot, update, streamed] = cli_digests(b"abc", algo);
+            assert_eq!(one_shot.1, update.1, "{:?}", algo);
+            assert_eq!(one_shot.1, streamed.1, "{:?}", algo);
+        }
+    }
+}
@@ -0,0 +1,600 @@
+//! `hash keygen`, `hash sign` and `hash verify-sig`: detached Ed25519 signatures over
+//! manifests, in the file formats of minisign.
+//!
+//! * public key: `base64("Ed" || key id || public key)` after an untrusted comment line;
+//! * secret key: `base64("Ed" || kdf || "B2" || salt || opslimit || memlimit ||
+//!   key id || keypair || checksum)`, the checksum being BLAKE2b-256 of
+//!   `"Ed" || key id || keypair`. With the `Sc` kdf the last three fields are
+//!   XORed with scrypt of the password, as minisign writes them by default. `keygen`
+//!   writes unencrypted keys, like `minisign -G -W`;
+//! * signature: an untrusted comment, `base64("ED" || key id || Ed25519(BLAKE2b-512(file)))`,
+//!   a trusted comment and `base64(Ed25519(signature || trusted comment))`. Legacy
+//!   `Ed` signatures over the raw file are accepted when verifying.
+//!
+//! `minisign -Vm <manifest> -p <key>.pub` checks what `hash sign` writes, and the other
+//! way round.
+
+use crate::diff::{self, Manifest};
+use crate::select::FileFilter;
+use crate::{bytes_to_hex_string, manifest};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Blake2b512, Digest};
+use clap::{Arg, ArgMatches, Command};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SIG_ALG: &[u8; 2] = b"Ed";
+const SIG_ALG_HASHED: &[u8; 2] = b"ED";
+const KDF_NONE: &[u8; 2] = &[0, 0];
+const KDF_SCRYPT: &[u8; 2] = b"Sc";
+const CHK_ALG: &[u8; 2] = b"B2";
+const SECRET_KEY_LEN: usize = 158;
+
+fn file_arg(name: &'static str, help: &'static str) -> Arg<'static> {
+    Arg::new(name)
+        .long(name)
+        .value_name("file")
+        .help(help)
+        .takes_value(true)
+}
+
+fn tree_args(command: Command<'static>, help: &'static str) -> Command<'static> {
+    command
+        .arg(
+            Arg::new("tree")
+                .short('t')
+                .long("tree")
+                .value_name("dir")
+                .help(help)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("exclude")
+                .short('x')
+                .long("exclude")
+                .value_name("pattern")
+                .help("Skip files of the --tree matching this glob pattern. Can be provided multiple times")
+                .takes_value(true)
+                .multiple_occurrences(true)
+        )
+        .arg(
+            Arg::new("gitignore")
+                .long("gitignore")
+                .help("Skip files of the --tree ignored by .gitignore, .ignore and .git/info/exclude, and VCS directories")
+        )
+}
+
+pub fn keygen_command() -> Command<'static> {
+    Command::new("keygen")
+        .about("Generate an unencrypted Ed25519 key pair for sign and verify-sig, in minisign format. Meant for tests and automation")
+        .arg(file_arg("public-key", "Where to write the public key, hash.pub by default").short('p'))
+        .arg(file_arg("secret-key", "Where to write the secret key, hash.key by default").short('s'))
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .help("Overwrite existing key files")
+        )
+}
+
+pub fn sign_command() -> Command<'static> {
+    let command = Command::new("sign")
+        .about("Write a detached minisign signature of a manifest to <manifest>.minisig")
+        .arg(
+            Arg::new("manifest")
+                .value_name("manifest")
+                .help("Manifest to sign, written first when --tree is given")
+                .required(true)
+        )
+        .arg(file_arg("secret-key", "Secret key, in minisign format. The password of an encrypted key is read from stdin").short('s').required(true))
+        .arg(file_arg("signature", "Where to write the signature, <manifest>.minisig by default"))
+        .arg(
+            Arg::new("comment")
+                .short('c')
+                .long("comment")
+                .value_name("text")
+                .help("Trusted comment, signed along with the manifest. Defaults to the time and file name")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::new("force")
+                .short('f')
+                .long("force")
+                .help("Overwrite an existing signature, and with --tree an existing manifest")
+        );
+    tree_args(command, "Write a manifest of this directory, with paths relative to it and an algo= comment naming the algorithm, before signing")
+}
+
+pub fn verify_command() -> Command<'static> {
+    let command = Command::new("verify-sig")
+        .about("Check the minisign signature of a manifest. Exits with 0 when it is valid and 1 when it is not")
+        .arg(
+            Arg::new("manifest")
+                .value_name("manifest")
+                .help("Signed manifest")
+                .required(true)
+        )
+        .arg(file_arg("public-key", "Public key, in minisign format").short('p').required(true))
+        .arg(file_arg("signature", "Signature, <manifest>.minisig by default"));
+    tree_args(command, "Once the signature is valid, also check that this directory matches the manifest")
+}
+
+fn fail(code: i32, err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(code);
+}
+
+fn key_id_string(key_id: &[u8]) -> String {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(key_id);
+    format!("{:016X}", u64::from_le_bytes(bytes))
+}
+
+/// The base64 payload of a key file: the first line that is not a comment.
+fn read_key_file(path: &str, what: &str) -> Vec<u8> {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot read {} {}: {}", what, path, e)));
+    let line = content
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with("untrusted comment:"))
+        .unwrap_or_default();
+    STANDARD
+        .decode(line)
+        .unwrap_or_else(|_| fail(exitcode::DATAERR, format!("{} is not a minisign {}", path, what)))
+}
+
+struct PublicKey {
+    key_id: [u8; 8],
+    key: VerifyingKey,
+}
+
+fn read_public_key(path: &str) -> PublicKey {
+    let bytes = read_key_file(path, "public key");
+    let parsed = (bytes.len() == 42 && &bytes[..2] == SIG_ALG)
+        .then(|| VerifyingKey::from_bytes(bytes[10..].try_into().unwrap()).ok())
+        .flatten();
+    let Some(key) = parsed else {
+        fail(exitcode::DATAERR, format!("{} is not a minisign public key", path));
+    };
+    PublicKey { key_id: bytes[2..10].try_into().unwrap(), key }
+}
+
+/// scrypt parameters from libsodium's opslimit and memlimit, as
+/// `crypto_pwhash_scryptsalsa208sha256` picks them.
+fn scrypt_params(opslimit: u64, memlimit: u64) -> Option<scrypt::Params> {
+    let opslimit = opslimit.max(32768);
+    let r = 8u64;
+    let log_n = |max_n: u64| (1..63).find(|n| (1u64 << n) > max_n / 2).unwrap_or(63);
+    let (log_n, p) = if opslimit < memlimit / 32 {
+        (log_n(opslimit / (r * 4)), 1)
+    } else {
+        let log_n = log_n(memlimit / (r * 128));
+        let max_rp = ((opslimit / 4) >> log_n).min(0x3fffffff);
+        (log_n, max_rp / r)
+    };
+    // The length only matters for PHC strings, scrypt() fills whatever it is given.
+    scrypt::Params::new(log_n as u8, r as u32, p as u32, scrypt::Params::RECOMMENDED_LEN).ok()
+}
+
+fn read_secret_key(path: &str) -> (SigningKey, [u8; 8]) {
+    let mut bytes = read_key_file(path, "secret key");
+    if bytes.len() != SECRET_KEY_LEN || &bytes[..2] != SIG_ALG || &bytes[4..6] != CHK_ALG {
+        fail(exitcode::DATAERR, format!("{} is not a minisign secret key", path));
+    }
+    let (head, sealed) = bytes.split_at_mut(54);
+    match &head[2..4] {
+        kdf if kdf == KDF_NONE => {}
+        kdf if kdf == KDF_SCRYPT => {
+            eprint!("Password for {}: ", path);
+            let password = crate::kdf::read_password();
+            let opslimit = u64::from_le_bytes(head[38..46].try_into().unwrap());
+            let memlimit = u64::from_le_bytes(head[46..54].try_into().unwrap());
+            let Some(params) = scrypt_params(opslimit, memlimit) else {
+                fail(exitcode::DATAERR, format!("{} has unsupported scrypt limits", path));
+            };
+            let mut stream = vec![0u8; sealed.len()];
+            if scrypt::scrypt(password.as_bytes(), &head[6..38], &params, &mut stream).is_err() {
+                fail(exitcode::DATAERR, format!("{} has unsupported scrypt limits", path));
+            }
+            sealed.iter_mut().zip(stream).for_each(|(b, k)| *b ^= k);
+        }
+        _ => fail(exitcode::DATAERR, format!("{} is encrypted with an unknown key derivation", path)),
+    }
+
+    let (key_id, rest) = sealed.split_at(8);
+    let (keypair, checksum) = rest.split_at(64);
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update(SIG_ALG);
+    hasher.update(key_id);
+    hasher.update(keypair);
+    if hasher.finalize().as_slice() != checksum {
+        fail(exitcode::DATAERR, format!("Wrong password for {}, or the key is damaged", path));
+    }
+    let Ok(key) = SigningKey::from_keypair_bytes(keypair.try_into().unwrap()) else {
+        fail(exitcode::DATAERR, format!("{} holds an inconsistent key pair", path));
+    };
+    (key, key_id.try_into().unwrap())
+}
+
+/// Create `path` for writing, refusing to replace an existing file unless `force`.
+fn create(path: &str, force: bool, private: bool) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let _ = private;
+    options.open(path)
+}
+
+fn cannot_create(path: &str, err: std::io::Error) -> ! {
+    let hint = if err.kind() == std::io::ErrorKind::AlreadyExists { ", use --force to overwrite it" } else { "" };
+    fail(exitcode::CANTCREAT, format!("Cannot create {}: {}{}", path, err, hint))
+}
+
+/// Contents of the unencrypted secret key file and the public key file for `seed`.
+fn key_files(seed: &[u8; 32], key_id: &[u8; 8]) -> (String, String) {
+    let key = SigningKey::from_bytes(seed);
+    let keypair = key.to_keypair_bytes();
+
+    let mut secret = Vec::with_capacity(SECRET_KEY_LEN);
+    secret.extend_from_slice(SIG_ALG);
+    secret.extend_from_slice(KDF_NONE);
+    secret.extend_from_slice(CHK_ALG);
+    secret.extend_from_slice(&[0u8; 48]);
+    secret.extend_from_slice(key_id);
+    secret.extend_from_slice(&keypair);
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update(SIG_ALG);
+    hasher.update(key_id);
+    hasher.update(keypair);
+    secret.extend_from_slice(&hasher.finalize());
+
+    let mut public = Vec::with_capacity(42);
+    public.extend_from_slice(SIG_ALG);
+    public.extend_from_slice(key_id);
+    public.extend_from_slice(key.verifying_key().as_bytes());
+
+    let id = key_id_string(key_id);
+    (
+        format!("untrusted comment: minisign unencrypted secret key {}\n{}\n", id, STANDARD.encode(&secret)),
+        format!("untrusted comment: minisign public key {}\n{}\n", id, STANDARD.encode(&public)),
+    )
+}
+
+pub fn run_keygen(matches: &ArgMatches) {
+    let public_path = matches.value_of("public-key").unwrap_or("hash.pub");
+    let secret_path = matches.value_of("secret-key").unwrap_or("hash.key");
+    let force = matches.is_present("force");
+
+    let mut seed = [0u8; 32];
+    let mut key_id = [0u8; 8];
+    if let Err(err) = getrandom::getrandom(&mut seed).and_then(|_| getrandom::getrandom(&mut key_id)) {
+        fail(exitcode::OSERR, format!("Cannot generate a key: {}", err));
+    }
+    let (secret, public) = key_files(&seed, &key_id);
+    // Both files are created before either is written, so that a public key which
+    // cannot be created does not leave a lone secret key behind.
+    let mut secret_file = create(secret_path, force, true).unwrap_or_else(|e| cannot_create(secret_path, e));
+    let mut public_file = create(public_path, force, false).unwrap_or_else(|e| {
+        let _ = std::fs::remove_file(secret_path);
+        cannot_create(public_path, e)
+    });
+    let written = secret_file
+        .write_all(secret.as_bytes())
+        .and_then(|_| public_file.write_all(public.as_bytes()));
+    if let Err(err) = written {
+        fail(exitcode::IOERR, format!("Cannot write key: {}", err));
+    }
+    println!("[SECRET KEY] [{}]\n[PUBLIC KEY] [{}]\n[KEY ID] [{}]", secret_path, public_path, key_id_string(&key_id));
+}
+
+/// BLAKE2b-512 of the message, which is what a prehashed ("ED") signature covers.
+fn prehash(message: &[u8]) -> Vec<u8> {
+    Blake2b512::digest(message).to_vec()
+}
+
+/// Replace `path` with `content` through a temporary file, so that it is never left
+/// half written.
+fn write_atomic(path: &str, content: &[u8]) -> std::io::Result<()> {
+    let tmp = format!("{}.tmp{}", path, std::process::id());
+    let written = File::create(&tmp)
+        .and_then(|mut file| file.write_all(content).and_then(|_| file.sync_all()))
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    written
+}
+
+fn hash_tree(matches: &ArgMatches, dir: &str, algo: crate::HashAlgorithm) -> Manifest {
+    diff::hash_dir(dir, algo, &FileFilter::from_matches(matches)).unwrap_or_else(|e| fail(exitcode::IOERR, e))
+}
+
+fn signature_path(matches: &ArgMatches, manifest: &str) -> String {
+    match matches.value_of("signature") {
+        Some(path) => path.to_string(),
+        None => format!("{}.minisig", manifest),
+    }
+}
+
+/// A prehashed minisign signature of `message`, as the text of a `.minisig` file.
+fn signature_file(key: &SigningKey, key_id: &[u8; 8], message: &[u8], comment: &str) -> String {
+    let signature = key.sign(&prehash(message)).to_bytes();
+    let mut global = signature.to_vec();
+    global.extend_from_slice(comment.as_bytes());
+    let global = key.sign(&global).to_bytes();
+
+    let mut blob = SIG_ALG_HASHED.to_vec();
+    blob.extend_from_slice(key_id);
+    blob.extend_from_slice(&signature);
+    format!(
+        "untrusted comment: signature from hash secret key {}\n{}\ntrusted comment: {}\n{}\n",
+        key_id_string(key_id),
+        STANDARD.encode(&blob),
+        comment,
+        STANDARD.encode(global)
+    )
+}
+
+pub fn run_sign(matches: &ArgMatches) {
+    let manifest_path = matches.value_of("manifest").unwrap();
+    let (key, key_id) = read_secret_key(matches.value_of("secret-key").unwrap());
+
+    let force = matches.is_present("force");
+    let signature_path = signature_path(matches, manifest_path);
+    if !force && Path::new(&signature_path).exists() {
+        fail(exitcode::CANTCREAT, format!("Cannot create {}: the file exists, use --force to overwrite it", signature_path));
+    }
+
+    if let Some(dir) = matches.value_of("tree") {
+        if !force && Path::new(manifest_path).exists() {
+            fail(exitcode::CANTCREAT, format!("Cannot create {}: the file exists, use --force to overwrite it", manifest_path));
+        }
+        let algo = crate::get_algorithm(matches);
+        let entries = hash_tree(matches, dir, algo);
+        // Untagged SHA256 and BLAKE3 digests look alike, so the manifest names its algorithm.
+        let mut out = format!("{}{}\n", diff::ALGO_HEADER, algo.flag_name());
+        for (path, digest) in entries.iter() {
+            out.push_str(&manifest::format_line(&bytes_to_hex_string(digest), path));
+            out.push('\n');
+        }
+        if let Err(err) = write_atomic(manifest_path, out.as_bytes()) {
+            fail(exitcode::CANTCREAT, format!("Cannot write manifest {}: {}", manifest_path, err));
+        }
+    }
+
+    let content = std::fs::read(manifest_path).unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot read {}: {}", manifest_path, e)));
+    let comment = match matches.value_of("comment") {
+        Some(comment) => comment.to_string(),
+        None => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            let name = Path::new(manifest_path).file_name().map_or(manifest_path.into(), |n| n.to_string_lossy());
+            format!("timestamp:{}\tfile:{}\tprehashed", now, name)
+        }
+    };
+    if comment.contains(['\r', '\n']) {
+        fail(exitcode::USAGE, "The trusted comment must be a single line");
+    }
+
+    if let Err(err) = write_atomic(&signature_path, signature_file(&key, &key_id, &content, &comment).as_bytes()) {
+        fail(exitcode::CANTCREAT, format!("Cannot write signature {}: {}", signature_path, err));
+    }
+    if !matches.is_present("quiet") {
+        println!("[SIGNED FILE] [{}]\n[SIGNATURE] [{}]", manifest_path, signature_path);
+    }
+}
+
+struct SignatureFile {
+    hashed: bool,
+    key_id: [u8; 8],
+    signature: Signature,
+    comment: String,
+    global: Signature,
+}
+
+fn parse_signature(content: &str) -> Option<SignatureFile> {
+    let lines: Vec<&str> = content.lines().collect();
+    (|| {
+        let blob = STANDARD.decode(lines.get(1)?.trim()).ok()?;
+        let comment = lines.get(2)?.strip_prefix("trusted comment: ")?;
+        let global: [u8; 64] = STANDARD.decode(lines.get(3)?.trim()).ok()?.try_into().ok()?;
+        if blob.len() != 74 || (&blob[..2] != SIG_ALG && &blob[..2] != SIG_ALG_HASHED) {
+            return None;
+        }
+        Some(SignatureFile {
+            hashed: &blob[..2] == SIG_ALG_HASHED,
+            key_id: blob[2..10].try_into().ok()?,
+            signature: Signature::from_bytes(blob[10..].try_into().ok()?),
+            comment: comment.to_string(),
+            global: Signature::from_bytes(&global),
+        })
+    })()
+}
+
+fn read_signature(path: &str) -> SignatureFile {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot read signature {}: {}", path, e)));
+    parse_signature(&content).unwrap_or_else(|| fail(exitcode::DATAERR, format!("{} is not a minisign signature", path)))
+}
+
+/// Whether both the signature of `message` and the global signature over the trusted
+/// comment are valid for `key`.
+fn is_valid(key: &PublicKey, signature: &SignatureFile, message: &[u8]) -> bool {
+    let digest;
+    let message = if signature.hashed {
+        digest = prehash(message);
+        &digest
+    } else {
+        message
+    };
+    let mut global = signature.signature.to_bytes().to_vec();
+    global.extend_from_slice(signature.comment.as_bytes());
+    key.key.verify_strict(message, &signature.signature).is_ok() && key.key.verify_strict(&global, &signature.global).is_ok()
+}
+
+/// Returns whether the signature is valid and, with `--tree`, the directory matches
+/// the manifest.
+pub fn run_verify(matches: &ArgMatches) -> bool {
+    let manifest_path = matches.value_of("manifest").unwrap();
+    let key = read_public_key(matches.value_of("public-key").unwrap());
+    let signature = read_signature(&signature_path(matches, manifest_path));
+    let quiet = matches.is_present("quiet");
+
+    if signature.key_id != key.key_id {
+        println!(
+            "[INVALID SIGNATURE] [{}] [signed with key {}, not {}]",
+            manifest_path,
+            key_id_string(&signature.key_id),
+            key_id_string(&key.key_id)
+        );
+        return false;
+    }
+    // The manifest is read once, so the bytes checked against the tree are the ones
+    // whose signature was verified.
+    let content = std::fs::read(manifest_path).unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot read {}: {}", manifest_path, e)));
+    if !is_valid(&key, &signature, &content) {
+        println!("[INVALID SIGNATURE] [{}]", manifest_path);
+        return false;
+    }
+    if !quiet {
+        println!(
+            "[VALID SIGNATURE] [{}]\n[KEY ID] [{}]\n[TRUSTED COMMENT] [{}]",
+            manifest_path,
+            key_id_string(&key.key_id),
+            signature.comment
+        );
+    }
+
+    let Some(dir) = matches.value_of("tree") else {
+        return true;
+    };
+    let signed = diff::parse_manifest_bytes(manifest_path, &content, 0).unwrap_or_else(|e| fail(exitcode::DATAERR, e));
+    let algo = diff::pick_algorithm(matches, &[(manifest_path, &signed)]).unwrap_or_else(|e| fail(exitcode::DATAERR, e));
+    let signed = signed.entries;
+    if signed.values().any(|d| d.len() != algo.output_len()) {
+        fail(exitcode::DATAERR, format!("{} does not hold {:?} digests", manifest_path, algo));
+    }
+    let actual = hash_tree(matches, dir, algo);
+    let result = diff::diff(&signed, &actual);
+    if result.is_empty() {
+        if !quiet {
+            println!("[MATCH TREE] [{}]", dir);
+        }
+        return true;
+    }
+    println!("[MISMATCH TREE] [{}]", dir);
+    diff::print_changes(&result, &actual);
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; 32] = [7; 32];
+    const KEY_ID: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    fn keys() -> (SigningKey, PublicKey) {
+        let (secret, public) = key_files(&SEED, &KEY_ID);
+        let dir = tempfile::tempdir().unwrap();
+        let secret_path = dir.path().join("hash.key");
+        let public_path = dir.path().join("hash.pub");
+        std::fs::write(&secret_path, secret).unwrap();
+        std::fs::write(&public_path, public).unwrap();
+        let (key, key_id) = read_secret_key(&secret_path.to_string_lossy());
+        let public = read_public_key(&public_path.to_string_lossy());
+        assert_eq!(key_id, KEY_ID);
+        (key, public)
+    }
+
+    #[test]
+    fn key_files_round_trip() {
+        let (key, public) = keys();
+        assert_eq!(key.to_bytes(), SEED);
+        assert_eq!(public.key_id, KEY_ID);
+        assert_eq!(public.key, key.verifying_key());
+        assert_eq!(key_id_string(&KEY_ID), "0807060504030201");
+    }
+
+    #[test]
+    fn signatures_verify_with_minisign() {
+        let (key, _) = keys();
+        let (_, public) = key_files(&SEED, &KEY_ID);
+        let message = b"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  empty\n";
+        let signature = signature_file(&key, &KEY_ID, message, "file:manifest.txt");
+
+        let public = minisign_verify::PublicKey::decode(&public).unwrap();
+        let decoded = minisign_verify::Signature::decode(&signature).unwrap();
+        assert_eq!(decoded.trusted_comment(), "file:manifest.txt");
+        assert!(public.verify(message, &decoded, false).is_ok());
+        assert!(public.verify(b"something else", &decoded, false).is_err());
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let (key, public) = keys();
+        let signature = parse_signature(&signature_file(&key, &KEY_ID, b"manifest", "comment")).unwrap();
+        assert!(signature.hashed);
+        assert!(is_valid(&public, &signature, b"manifest"));
+        assert!(!is_valid(&public, &signature, b"manifesT"));
+        let forged = SignatureFile { comment: "other comment".to_string(), ..signature };
+        assert!(!is_valid(&public, &forged, b"manifest"));
+        assert!(parse_signature("untrusted comment: x\nAAAA\n").is_none());
+    }
+
+    #[test]
+    fn accepts_legacy_signatures() {
+        let (key, public) = keys();
+        let signature = key.sign(b"manifest");
+        let global = key.sign(&[signature.to_bytes().as_slice(), b"comment"].concat());
+        let legacy = SignatureFile { hashed: false, key_id: KEY_ID, signature, comment: "comment".to_string(), global };
+        assert!(is_valid(&public, &legacy, b"manifest"));
+    }
+
+    #[test]
+    fn signed_trees_verify_with_every_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let (secret, public) = key_files(&SEED, &KEY_ID);
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        std::fs::write(path("hash.key"), secret).unwrap();
+        std::fs::write(path("hash.pub"), public).unwrap();
+        std::fs::create_dir(path("tree")).unwrap();
+        std::fs::write(path("tree/file"), "content").unwrap();
+        for algo in crate::HashAlgorithm::ALL {
+            let flag = format!("--{}", algo.flag_name());
+            let manifest = path(&format!("{}.txt", algo.flag_name()));
+            let run = |args: &[&str]| crate::build_app().get_matches_from(["hash", "-q", &flag].iter().chain(args));
+            let matches = run(&["sign", "-s", &path("hash.key"), "--tree", &path("tree"), &manifest]);
+            run_sign(matches.subcommand_matches("sign").unwrap());
+            assert!(std::fs::read_to_string(&manifest).unwrap().starts_with(&format!("# algo={}\n", algo.flag_name())));
+            let matches = crate::build_app().get_matches_from(["hash", "-q", "verify-sig", "-p", &path("hash.pub"), "--tree", &path("tree"), &manifest]);
+            assert!(run_verify(matches.subcommand_matches("verify-sig").unwrap()), "{:?}", algo);
+        }
+        // Signing again with --force replaces the manifest and the signature.
+        let manifest = path("sha256.txt");
+        let matches = crate::build_app().get_matches_from(["hash", "-q", "sign", "-f", "-c", "again", "-s", &path("hash.key"), "--tree", &path("tree"), &manifest]);
+        run_sign(matches.subcommand_matches("sign").unwrap());
+        let signature = read_signature(&format!("{}.minisig", manifest));
+        assert_eq!(signature.comment, "again");
+        assert!(std::fs::read_dir(dir.path()).unwrap().all(|e| !e.unwrap().file_name().to_string_lossy().contains(".tmp")));
+    }
+
+    #[test]
+    fn scrypt_limits_like_libsodium() {
+        // minisign's defaults: opslimit 2^25, memlimit 2^30.
+        let params = scrypt_params(1 << 25, 1 << 30).unwrap();
+        assert_eq!((params.log_n(), params.r(), params.p()), (20, 8, 1));
+    }
+}
@@ -0,0 +1,318 @@
+//! ssdeep context-triggered piecewise hashes, computed and compared as libfuzzy does.
+//!
+//! A rolling hash over the last 7 bytes splits the input wherever it hits
+//! `-1 mod blocksize`, and every piece contributes one base64 character (the low 6 bits
+//! of its FNV hash). The digest is `blocksize:pieces:pieces at twice the blocksize`,
+//! the blocksize being the smallest `3 * 2^n` that yields 32 to 64 characters. Pieces of
+//! every blocksize are tracked at once, so the input is read a single time.
+
+const ROLLING_WINDOW: usize = 7;
+const MIN_BLOCKSIZE: u64 = 3;
+const HASH_PRIME: u32 = 0x0100_0193;
+const HASH_INIT: u32 = 0x2802_1967;
+const SPAMSUM_LENGTH: usize = 64;
+const NUM_BLOCKHASHES: usize = 31;
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn block_size(index: usize) -> u64 {
+    MIN_BLOCKSIZE << index
+}
+
+fn sum_hash(c: u8, h: u32) -> u32 {
+    h.wrapping_mul(HASH_PRIME) ^ c as u32
+}
+
+fn b64(h: u32) -> u8 {
+    B64[h as usize % 64]
+}
+
+#[derive(Clone, Debug, Default)]
+struct Roll {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl Roll {
+    fn update(&mut self, c: u8) {
+        let c32 = c as u32;
+        self.h2 = self.h2.wrapping_sub(self.h1).wrapping_add(ROLLING_WINDOW as u32 * c32);
+        self.h1 = self.h1.wrapping_add(c32).wrapping_sub(self.window[self.n] as u32);
+        self.window[self.n] = c;
+        self.n = (self.n + 1) % ROLLING_WINDOW;
+        self.h3 = (self.h3 << 5) ^ c32;
+    }
+
+    fn sum(&self) -> u32 {
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct BlockHash {
+    h: u32,
+    halfh: u32,
+    digest: Vec<u8>,
+    /// Character of everything after the 63rd piece, once there is such a piece.
+    tail: Option<u8>,
+    /// Character of everything after the 31st piece.
+    halfdigest: Option<u8>,
+}
+
+impl BlockHash {
+    fn new(h: u32, halfh: u32) -> Self {
+        BlockHash { h, halfh, digest: Vec::with_capacity(SPAMSUM_LENGTH), tail: None, halfdigest: None }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Ssdeep {
+    roll: Roll,
+    blocks: Vec<BlockHash>,
+    /// Hash of everything since the last piece of the largest blocksize, once all
+    /// blocksizes are in use.
+    lasth: Option<u32>,
+    total: u64,
+}
+
+impl Default for Ssdeep {
+    fn default() -> Self {
+        Ssdeep { roll: Roll::default(), blocks: vec![BlockHash::new(HASH_INIT, HASH_INIT)], lasth: None, total: 0 }
+    }
+}
+
+impl Ssdeep {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking the next blocksize, which has seen the same pieces so far.
+    fn fork(&mut self) {
+        let last = self.blocks.last().unwrap();
+        if self.blocks.len() < NUM_BLOCKHASHES {
+            self.blocks.push(BlockHash::new(last.h, last.halfh));
+        } else if self.lasth.is_none() {
+            self.lasth = Some(last.h);
+        }
+    }
+
+    fn step(&mut self, c: u8) {
+        self.roll.update(c);
+        for block in self.blocks.iter_mut() {
+            block.h = sum_hash(c, block.h);
+            block.halfh = sum_hash(c, block.halfh);
+        }
+        if let Some(h) = self.lasth.as_mut() {
+            *h = sum_hash(c, *h);
+        }
+
+        let trigger = self.roll.sum() as u64 + 1;
+        let mut i = 0;
+        while i < self.blocks.len() && trigger.is_multiple_of(block_size(i)) {
+            if self.blocks[i].digest.is_empty() {
+                self.fork();
+            }
+            let block = &mut self.blocks[i];
+            let c = b64(block.h);
+            block.halfdigest = Some(b64(block.halfh));
+            if block.digest.len() < SPAMSUM_LENGTH - 1 {
+                block.digest.push(c);
+                block.h = HASH_INIT;
+                if block.digest.len() < SPAMSUM_LENGTH / 2 {
+                    block.halfh = HASH_INIT;
+                    block.halfdigest = None;
+                }
+            } else {
+                block.tail = Some(c);
+            }
+            i += 1;
+        }
+    }
+
+    pub fn update(&mut self, input: &[u8]) {
+        self.total += input.len() as u64;
+        for &c in input {
+            self.step(c);
+        }
+    }
+
+    pub fn digest(&self) -> Result<String, String> {
+        let mut bi = 0;
+        while block_size(bi) * (SPAMSUM_LENGTH as u64) < self.total {
+            bi += 1;
+            if bi >= NUM_BLOCKHASHES {
+                return Err("Input is too large for an ssdeep hash".to_string());
+            }
+        }
+        bi = bi.min(self.blocks.len() - 1);
+        while bi > 0 && self.blocks[bi].digest.len() < SPAMSUM_LENGTH / 2 {
+            bi -= 1;
+        }
+
+        let pending = self.roll.sum() != 0;
+        let block = &self.blocks[bi];
+        let mut out = block.digest.clone();
+        if pending {
+            out.push(b64(block.h));
+        } else if let Some(c) = block.tail {
+            out.push(c);
+        }
+        out.push(b':');
+        if let Some(next) = self.blocks.get(bi + 1) {
+            out.extend_from_slice(&next.digest[..next.digest.len().min(SPAMSUM_LENGTH / 2 - 1)]);
+            if pending {
+                out.push(b64(next.halfh));
+            } else if let Some(c) = next.halfdigest {
+                out.push(c);
+            }
+        } else if pending {
+            out.push(b64(if bi == 0 { block.h } else { self.lasth.unwrap_or(block.h) }));
+        }
+        Ok(format!("{}:{}", block_size(bi), String::from_utf8(out).unwrap()))
+    }
+}
+
+struct Parsed {
+    block_size: u64,
+    first: Vec<u8>,
+    second: Vec<u8>,
+}
+
+/// Runs of more than three identical characters carry little information and are cut
+/// down to three before comparing.
+fn eliminate_sequences(s: &str) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(s.len());
+    for c in s.bytes() {
+        if out.len() < 3 || out[out.len() - 3..].iter().any(|&p| p != c) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Parse `blocksize:pieces:pieces`, ignoring the `,"file name"` of ssdeep's output.
+fn parse(digest: &str) -> Option<Parsed> {
+    let (size, rest) = digest.trim().split_once(':')?;
+    let (first, second) = rest.split_once(':')?;
+    let second = second.split(',').next().unwrap_or_default();
+    let block_size: u64 = size.parse().ok()?;
+    let valid = |s: &str| s.bytes().all(|c| B64.contains(&c));
+    if block_size < MIN_BLOCKSIZE || !valid(first) || !valid(second) {
+        return None;
+    }
+    Some(Parsed { block_size, first: eliminate_sequences(first), second: eliminate_sequences(second) })
+}
+
+pub fn is_digest(digest: &str) -> bool {
+    parse(digest).is_some()
+}
+
+fn has_common_substring(a: &[u8], b: &[u8]) -> bool {
+    a.windows(ROLLING_WINDOW).any(|w| b.windows(ROLLING_WINDOW).any(|v| v == w))
+}
+
+/// Edit distance with insertions and deletions costing 1 and substitutions 2.
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let replace = prev + if ca == cb { 0 } else { 2 };
+            prev = row[j + 1];
+            row[j + 1] = replace.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn score_strings(a: &[u8], b: &[u8], block_size: u64) -> u64 {
+    if a.len() > SPAMSUM_LENGTH || b.len() > SPAMSUM_LENGTH || !has_common_substring(a, b) {
+        return 0;
+    }
+    let distance = edit_distance(a, b) as u64;
+    let scaled = distance * SPAMSUM_LENGTH as u64 / (a.len() + b.len()) as u64;
+    let score = 100u64.saturating_sub(100 * scaled / SPAMSUM_LENGTH as u64);
+    // Small blocksizes on short inputs would otherwise claim matches they cannot back.
+    if block_size >= (99 + ROLLING_WINDOW as u64) / ROLLING_WINDOW as u64 * MIN_BLOCKSIZE {
+        return score;
+    }
+    score.min(block_size / MIN_BLOCKSIZE * a.len().min(b.len()) as u64)
+}
+
+/// Similarity of two ssdeep digests from 0 to 100, as `ssdeep -d` reports it.
+pub fn compare(a: &str, b: &str) -> Result<u32, String> {
+    let a = parse(a).ok_or_else(|| format!("'{}' is not an ssdeep digest", a))?;
+    let b = parse(b).ok_or_else(|| format!("'{}' is not an ssdeep digest", b))?;
+    let score = if a.block_size == b.block_size {
+        if a.first == b.first && a.second == b.second {
+            return Ok(100);
+        }
+        score_strings(&a.first, &b.first, a.block_size).max(score_strings(&a.second, &b.second, a.block_size * 2))
+    } else if a.block_size.checked_mul(2) == Some(b.block_size) {
+        score_strings(&a.second, &b.first, b.block_size)
+    } else if b.block_size.checked_mul(2) == Some(a.block_size) {
+        score_strings(&a.first, &b.second, a.block_size)
+    } else {
+        0
+    };
+    Ok(score as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The examples of the python-ssdeep documentation, which wraps libfuzzy.
+    const CTPH_A: &str = "Also called fuzzy hashes, Ctph can match inputs that have homologies.";
+    const CTPH_B: &str = "Also called fuzzy hashes, CTPH can match inputs that have homologies.";
+
+    fn digest(input: &[u8]) -> String {
+        let mut state = Ssdeep::new();
+        state.update(input);
+        state.digest().unwrap()
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(digest(b""), "3::");
+        assert_eq!(digest(CTPH_A.as_bytes()), "3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C");
+        assert_eq!(digest(CTPH_B.as_bytes()), "3:AXGBicFlIHBGcL6wCrFQEv:AXGH6xLsr2C");
+    }
+
+    #[test]
+    fn known_score() {
+        let (a, b) = (digest(CTPH_A.as_bytes()), digest(CTPH_B.as_bytes()));
+        assert_eq!(compare(&a, &b), Ok(22));
+        assert_eq!(compare(&b, &a), Ok(22));
+        assert_eq!(compare(&a, &a), Ok(100));
+    }
+
+    #[test]
+    fn split_updates_match_one_update() {
+        let input: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut state = Ssdeep::new();
+        for chunk in input.chunks(997) {
+            state.update(chunk);
+        }
+        assert_eq!(state.digest().unwrap(), digest(&input));
+    }
+
+    #[test]
+    fn parses_ssdeep_output() {
+        assert!(is_digest("3:AXGBicFlgVNhBGcL6wCrFQEv:AXGHsNhxLsr2C,\"file.txt\""));
+        assert!(!is_digest("3:AXGB"));
+        assert!(!is_digest("2:AXGB:AXG"));
+        assert!(compare("3::", "nope").is_err());
+    }
+
+    #[test]
+    fn scoring_helpers() {
+        assert_eq!(eliminate_sequences("aaaaabbbbc"), b"aaabbbc");
+        // Two substitutions at 2 each and one insertion.
+        assert_eq!(edit_distance(b"kitten", b"sitting"), 5);
+        assert_eq!(edit_distance(b"", b"abc"), 3);
+    }
+}
@@ -0,0 +1,431 @@
+//! `hash store`: a content-addressed store for build artifacts.
+//!
+//! An object lives at `<store>/<algo>/<aa>/<digest>`, with `--fanout` levels of two
+//! hex characters each (one by default) between the algorithm and the digest. The
+//! fanout and the default algorithm are fixed when the store is created and recorded
+//! in `<store>/config`. Objects are first written to `<store>/tmp`, synced, and renamed
+//! into place, so an object path never holds a partial file, and the directory is
+//! synced after the rename. Objects are read-only.
+
+use crate::encoding::{decode_plain_hex, decode_tagged};
+use crate::{bytes_to_hex_string, get_algorithm, HashAlgorithm, HashImpl, EXIT_MISMATCH};
+use clap::{Arg, ArgMatches, Command};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const HEADER: &str = "# hash-store v1";
+const MAX_FANOUT: usize = 4;
+
+pub fn command() -> Command<'static> {
+    Command::new("store")
+        .about("Content-addressed store: add files under their digest, get them back, verify the stored objects")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("store")
+                .long("store")
+                .value_name("dir")
+                .help("Store directory. Defaults to $HASH_STORE, then .hash-store")
+                .takes_value(true)
+                .global(true)
+        )
+        .arg(
+            Arg::new("fanout")
+                .long("fanout")
+                .value_name("levels")
+                .help("Directory levels of two hex characters above each object, 0 to 4. Only used when the store is created, 1 by default")
+                .takes_value(true)
+                .global(true)
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(n) if n <= MAX_FANOUT => Ok(()),
+                    _ => Err(format!("should be a number from 0 to {}", MAX_FANOUT)),
+                })
+        )
+        .subcommand(
+            Command::new("add")
+                .about("Copy files into the store and print their digests")
+                .arg(
+                    Arg::new("files")
+                        .value_name("file")
+                        .help("Files to add")
+                        .required(true)
+                        .multiple_values(true)
+                )
+        )
+        .subcommand(
+            Command::new("get")
+                .about("Write a stored object to stdout or a file, writing nothing unless it matches its digest")
+                .arg(
+                    Arg::new("digest")
+                        .value_name("digest")
+                        .help("Hex digest of the object, or a digest naming its algorithm such as sha256:<hex>")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("file")
+                        .help("Write the object to this file, atomically, instead of stdout")
+                        .takes_value(true)
+                )
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Re-hash every object in the store. Exits with 1 when one does not match its name")
+        )
+}
+
+fn fail(code: i32, err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(code);
+}
+
+/// Reader that copies everything read through it to `out`.
+struct Tee<'a, R: Read, W: Write> {
+    inner: R,
+    out: &'a mut W,
+}
+
+impl<R: Read, W: Write> Read for Tee<'_, R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.out.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+/// A file next to `dir`'s other entries that nobody else can have opened.
+fn create_temp(dir: &Path) -> std::io::Result<(PathBuf, File)> {
+    let mut random = [0u8; 8];
+    getrandom::getrandom(&mut random).map_err(std::io::Error::other)?;
+    let path = dir.join(format!(".tmp-{}-{}", std::process::id(), bytes_to_hex_string(&random)));
+    let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+    Ok((path, file))
+}
+
+/// Sync `dir` itself, so that an entry renamed into it survives a crash.
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    let _ = dir;
+    Ok(())
+}
+
+/// Copy `reader` to a temporary file in `dir` while hashing it. The file is synced
+/// before the digest is returned.
+fn copy_to_temp(reader: impl Read, dir: &Path, algo: HashAlgorithm) -> std::io::Result<(PathBuf, Vec<u8>)> {
+    let (path, mut file) = create_temp(dir)?;
+    let result = HashImpl::digest_reader(&mut Tee { inner: reader, out: &mut file }, algo).and_then(|digest| {
+        file.sync_all()?;
+        Ok(digest)
+    });
+    match result {
+        Ok(digest) => Ok((path, digest)),
+        Err(err) => {
+            let _ = std::fs::remove_file(&path);
+            Err(err)
+        }
+    }
+}
+
+pub struct Store {
+    root: PathBuf,
+    fanout: usize,
+    algo: HashAlgorithm,
+}
+
+impl Store {
+    /// Open the store selected by `--store`, creating it when `create` is set.
+    fn open(matches: &ArgMatches, create: bool) -> Result<Self, String> {
+        let root = match matches.value_of("store") {
+            Some(dir) => PathBuf::from(dir),
+            None => std::env::var_os("HASH_STORE").map_or_else(|| PathBuf::from(".hash-store"), PathBuf::from),
+        };
+        let fanout: Option<usize> = matches.value_of_t("fanout").ok();
+        let config = root.join("config");
+        match std::fs::read_to_string(&config) {
+            Ok(text) => {
+                let store = Store::parse_config(root.clone(), &text)
+                    .ok_or_else(|| format!("{} is not a hash store config", config.display()))?;
+                if fanout.is_some_and(|f| f != store.fanout) {
+                    return Err(format!("{} was created with --fanout {}", root.display(), store.fanout));
+                }
+                Ok(store)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && create => {
+                let store = Store { root, fanout: fanout.unwrap_or(1), algo: get_algorithm(matches) };
+                store.init().map_err(|e| format!("Cannot create store {}: {}", store.root.display(), e))?;
+                Ok(store)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(format!("{} is not a hash store, add a file to create it", root.display()))
+            }
+            Err(err) => Err(format!("Cannot read {}: {}", config.display(), err)),
+        }
+    }
+
+    fn parse_config(root: PathBuf, text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next()? != HEADER {
+            return None;
+        }
+        let mut store = Store { root, fanout: 1, algo: HashAlgorithm::default() };
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            match line.split_once('=').map(|(k, v)| (k.trim(), v.trim()))? {
+                ("fanout", value) => store.fanout = value.parse().ok().filter(|f| *f <= MAX_FANOUT)?,
+                ("algorithm", value) => store.algo = value.parse().ok()?,
+                _ => return None,
+            }
+        }
+        Some(store)
+    }
+
+    fn init(&self) -> std::io::Result<()> {
+        std::fs::create_dir_all(self.root.join("tmp"))?;
+        let text = format!("{}\nfanout = {}\nalgorithm = {}\n", HEADER, self.fanout, self.algo.flag_name());
+        let (tmp, mut file) = create_temp(&self.root.join("tmp"))?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(tmp, self.root.join("config"))?;
+        sync_dir(&self.root)
+    }
+
+    /// The algorithm given on the command line, otherwise the store's default.
+    fn algorithm(&self, matches: &ArgMatches) -> HashAlgorithm {
+        if matches.is_present("md5") || matches.is_present("sha256") || matches.is_present("blake3") {
+            get_algorithm(matches)
+        } else {
+            self.algo
+        }
+    }
+
+    pub fn object_path(&self, algo: HashAlgorithm, digest: &[u8]) -> PathBuf {
+        let hex = bytes_to_hex_string(digest);
+        let mut path = self.root.join(algo.flag_name());
+        for level in 0..self.fanout {
+            path.push(&hex[level * 2..level * 2 + 2]);
+        }
+        path.push(hex);
+        path
+    }
+
+    /// Store `file`, returning its digest and whether it was already present.
+    pub fn add(&self, file: &Path, algo: HashAlgorithm) -> std::io::Result<(Vec<u8>, bool)> {
+        let tmp_dir = self.root.join("tmp");
+        std::fs::create_dir_all(&tmp_dir)?;
+        let (tmp, digest) = copy_to_temp(File::open(file)?, &tmp_dir, algo)?;
+        let target = self.object_path(algo, &digest);
+        if target.exists() {
+            std::fs::remove_file(&tmp)?;
+            return Ok((digest, true));
+        }
+        let placed = (|| {
+            let mut permissions = std::fs::metadata(&tmp)?.permissions();
+            permissions.set_readonly(true);
+            std::fs::set_permissions(&tmp, permissions)?;
+            std::fs::create_dir_all(target.parent().unwrap())?;
+            std::fs::rename(&tmp, &target)
+        })();
+        if placed.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        placed.and_then(|_| sync_dir(target.parent().unwrap())).map(|_| (digest, false))
+    }
+}
+
+fn run_add(store: &Store, matches: &ArgMatches) {
+    let algo = store.algorithm(matches);
+    let quiet = matches.is_present("quiet");
+    for file in matches.values_of("files").unwrap() {
+        match store.add(Path::new(file), algo) {
+            Ok((digest, existed)) => {
+                let hex = bytes_to_hex_string(&digest);
+                if quiet {
+                    println!("{}", hex);
+                } else {
+                    let action = if existed { "EXISTING" } else { "STORED" };
+                    println!("[{} FILE] [{}]\n[{:?} HASH] [{}]", action, file, algo, hex);
+                }
+            }
+            Err(err) => fail(exitcode::IOERR, format!("Cannot add {}: {}", file, err)),
+        }
+    }
+}
+
+/// The algorithm and bytes of a digest given to `get`: tagged digests name their
+/// algorithm, a plain hex one uses the selected algorithm.
+fn parse_digest(store: &Store, matches: &ArgMatches, digest: &str) -> (HashAlgorithm, Vec<u8>) {
+    if let Some((_, algo, bytes)) = decode_tagged(digest) {
+        return (algo, bytes);
+    }
+    let algo = store.algorithm(matches);
+    match decode_plain_hex(digest.trim()) {
+        Some(bytes) if bytes.len() == algo.output_len() => (algo, bytes),
+        _ => fail(exitcode::DATAERR, format!("'{}' is not a {:?} digest", digest, algo)),
+    }
+}
+
+fn run_get(store: &Store, matches: &ArgMatches) {
+    let (algo, digest) = parse_digest(store, matches, matches.value_of("digest").unwrap());
+    let path = store.object_path(algo, &digest);
+    let mut object = File::open(&path).unwrap_or_else(|e| {
+        let code = if e.kind() == std::io::ErrorKind::NotFound { EXIT_MISMATCH } else { exitcode::IOERR };
+        fail(code, format!("Cannot read object {}: {}", path.display(), e))
+    });
+    let corrupt = || format!("Object {} does not match its digest, run hash store verify", path.display());
+
+    let Some(output) = matches.value_of("output") else {
+        // Nothing written to stdout can be taken back, so the object is checked in a
+        // first pass and only then copied.
+        let actual = HashImpl::digest_reader(&mut object, algo)
+            .unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot read object {}: {}", path.display(), e)));
+        if actual != digest {
+            fail(exitcode::DATAERR, corrupt());
+        }
+        let copied = object
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| std::io::copy(&mut object, &mut std::io::stdout().lock()));
+        if let Err(err) = copied {
+            fail(exitcode::IOERR, format!("Cannot write object: {}", err));
+        }
+        return;
+    };
+
+    let output = Path::new(output);
+    let dir = match output.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let (tmp, actual) =
+        copy_to_temp(object, dir, algo).unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot write {}: {}", output.display(), e)));
+    if actual != digest {
+        let _ = std::fs::remove_file(&tmp);
+        fail(exitcode::DATAERR, corrupt());
+    }
+    if let Err(err) = std::fs::rename(&tmp, output) {
+        let _ = std::fs::remove_file(&tmp);
+        fail(exitcode::CANTCREAT, format!("Cannot write {}: {}", output.display(), err));
+    }
+    if let Err(err) = sync_dir(dir) {
+        fail(exitcode::IOERR, format!("Cannot sync {}: {}", dir.display(), err));
+    }
+}
+
+/// Returns whether every object matches the digest it is stored under.
+fn run_verify(store: &Store, matches: &ArgMatches) -> bool {
+    let quiet = matches.is_present("quiet");
+    let (mut matched, mut mismatched, mut unknown) = (0, 0, 0);
+    for algo in HashAlgorithm::ALL {
+        let dir = store.root.join(algo.flag_name());
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in WalkDir::new(&dir).sort_by_file_name() {
+            let entry = entry.unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot read store: {}", e)));
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let digest = path
+                .file_name()
+                .and_then(|n| decode_plain_hex(&n.to_string_lossy()))
+                .filter(|d| d.len() == algo.output_len() && store.object_path(algo, d) == path);
+            let Some(digest) = digest else {
+                unknown += 1;
+                println!("[UNKNOWN OBJECT] [{}]", path.display());
+                continue;
+            };
+            let actual = File::open(path)
+                .and_then(|mut f| HashImpl::digest_reader(&mut f, algo))
+                .unwrap_or_else(|e| fail(exitcode::IOERR, format!("Cannot read object {}: {}", path.display(), e)));
+            if actual == digest {
+                matched += 1;
+                if !quiet {
+                    println!("[MATCH OBJECT] [{}]", path.display());
+                }
+            } else {
+                mismatched += 1;
+                println!("[MISMATCH OBJECT] [{}]\n[{:?} HASH] [{}]", path.display(), algo, bytes_to_hex_string(&actual));
+            }
+        }
+    }
+    if !quiet {
+        println!("[SUMMARY] [{} MATCH] [{} MISMATCH] [{} UNKNOWN]", matched, mismatched, unknown);
+    }
+    mismatched == 0 && unknown == 0
+}
+
+/// Returns false when `verify` found damaged or misplaced objects.
+pub fn run(matches: &ArgMatches) -> bool {
+    let (name, sub_matches) = matches.subcommand().unwrap();
+    let store = Store::open(sub_matches, name == "add").unwrap_or_else(|e| fail(exitcode::DATAERR, e));
+    match name {
+        "add" => run_add(&store, sub_matches),
+        "get" => run_get(&store, sub_matches),
+        _ => return run_verify(&store, sub_matches),
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn config_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let store = Store { root: dir.join("store"), fanout: 2, algo: HashAlgorithm::BLAKE3 };
+        store.init().unwrap();
+        let text = std::fs::read_to_string(dir.join("store/config")).unwrap();
+        assert_eq!(text, "# hash-store v1\nfanout = 2\nalgorithm = blake3\n");
+        let parsed = Store::parse_config(store.root.clone(), &text).unwrap();
+        assert_eq!((parsed.fanout, parsed.algo), (2, HashAlgorithm::BLAKE3));
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        let root = PathBuf::from("store");
+        let defaults = Store::parse_config(root.clone(), "# hash-store v1\n").unwrap();
+        assert_eq!((defaults.fanout, defaults.algo), (1, HashAlgorithm::SHA256));
+        assert!(Store::parse_config(root.clone(), "").is_none());
+        assert!(Store::parse_config(root.clone(), "# hash-store v2\n").is_none());
+        assert!(Store::parse_config(root.clone(), "# hash-store v1\nfanout = 5\n").is_none());
+        assert!(Store::parse_config(root.clone(), "# hash-store v1\nalgorithm = crc32\n").is_none());
+        assert!(Store::parse_config(root, "# hash-store v1\ncolour = blue\n").is_none());
+    }
+
+    #[test]
+    fn object_paths_fan_out() {
+        let digest = decode_plain_hex(HELLO_SHA256).unwrap();
+        let store = Store { root: PathBuf::from("s"), fanout: 0, algo: HashAlgorithm::SHA256 };
+        assert_eq!(store.object_path(HashAlgorithm::SHA256, &digest), Path::new("s/sha256").join(HELLO_SHA256));
+        let store = Store { fanout: 2, ..store };
+        assert_eq!(store.object_path(HashAlgorithm::SHA256, &digest), Path::new("s/sha256/2c/f2").join(HELLO_SHA256));
+    }
+
+    #[test]
+    fn add_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let file = dir.join("hello.txt");
+        std::fs::write(&file, "hello").unwrap();
+        let store = Store { root: dir.join("store"), fanout: 1, algo: HashAlgorithm::SHA256 };
+        store.init().unwrap();
+
+        let (digest, existed) = store.add(&file, HashAlgorithm::SHA256).unwrap();
+        assert_eq!(bytes_to_hex_string(&digest), HELLO_SHA256);
+        assert!(!existed);
+        let object = store.object_path(HashAlgorithm::SHA256, &digest);
+        assert_eq!(std::fs::read(&object).unwrap(), b"hello");
+        assert!(std::fs::metadata(&object).unwrap().permissions().readonly());
+
+        let (again, existed) = store.add(&file, HashAlgorithm::SHA256).unwrap();
+        assert_eq!((again, existed), (digest, true));
+        assert_eq!(std::fs::read_dir(dir.join("store/tmp")).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,187 @@
+//! `--template`: user-defined output records.
+//!
+//! Placeholders are `{hash}`, `{algo}`, `{path}` (the path, or the text of a text
+//! input), `{basename}`, `{size}` (in bytes), `{type}` (`file`, `text` or `tree`),
+//! `{mtime}` (seconds since the Unix epoch) and `{index}` (position of the input,
+//! counting from 1). `\t`, `\n`, `\0` and `\\` are escapes, `{{` and `}}` are
+//! literal braces.
+
+use crate::HashInput;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Hash,
+    Algo,
+    Path,
+    Basename,
+    Size,
+    Type,
+    Mtime,
+    Index,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "hash" => Field::Hash,
+            "algo" => Field::Algo,
+            "path" => Field::Path,
+            "basename" => Field::Basename,
+            "size" => Field::Size,
+            "type" => Field::Type,
+            "mtime" => Field::Mtime,
+            "index" => Field::Index,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// What a record is made of.
+pub struct Record<'a> {
+    pub hash: &'a str,
+    pub algo: &'a str,
+    pub input: &'a HashInput<'a>,
+    pub index: usize,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('0') => literal.push('\0'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => return Err(format!("Unknown escape '\\{}' in template", other)),
+                    None => return Err("Template ends with a lone '\\'".to_string()),
+                },
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("Unterminated placeholder '{{{}' in template", name)),
+                        }
+                    }
+                    let field = Field::from_name(&name)
+                        .ok_or_else(|| format!("Unknown placeholder '{{{}}}' in template", name))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err("Unmatched '}' in template, write '}}' for a literal brace".to_string()),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    pub fn render(&self, record: &Record) -> String {
+        let (kind, name) = match record.input {
+            HashInput::Text(text) => ("text", *text),
+            HashInput::File(path) => ("file", *path),
+            HashInput::Tree(path) => ("tree", *path),
+        };
+        let metadata = match record.input {
+            HashInput::Text(_) => None,
+            _ => std::fs::metadata(name).ok(),
+        };
+        let mut out = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Field(Field::Hash) => out.push_str(record.hash),
+                Part::Field(Field::Algo) => out.push_str(record.algo),
+                Part::Field(Field::Path) => out.push_str(name),
+                Part::Field(Field::Basename) => match record.input {
+                    HashInput::Text(_) => out.push_str(name),
+                    _ => out.push_str(&Path::new(name).file_name().map_or(name.into(), |n| n.to_string_lossy())),
+                },
+                Part::Field(Field::Size) => match (record.input, &metadata) {
+                    (HashInput::Text(text), _) => out.push_str(&text.len().to_string()),
+                    (_, Some(m)) => out.push_str(&m.len().to_string()),
+                    _ => {}
+                },
+                Part::Field(Field::Type) => out.push_str(kind),
+                Part::Field(Field::Mtime) => {
+                    if let Some(secs) = metadata
+                        .as_ref()
+                        .and_then(|m| m.modified().ok())
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    {
+                        out.push_str(&secs.as_secs().to_string());
+                    }
+                }
+                Part::Field(Field::Index) => out.push_str(&(record.index + 1).to_string()),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, input: &HashInput) -> String {
+        let record = Record { hash: "abc123", algo: "sha256", input, index: 1 };
+        Template::parse(template).unwrap().render(&record)
+    }
+
+    #[test]
+    fn renders_text_records() {
+        let input = HashInput::Text("hello");
+        assert_eq!(render("{hash}  {path}\\n", &input), "abc123  hello\n");
+        assert_eq!(render("{index}\\t{algo}\\t{type}\\t{size}\\t{basename}\\0", &input), "2\tsha256\ttext\t5\thello\0");
+        assert_eq!(render("{{{hash}}} \\\\", &input), "{abc123} \\");
+        assert_eq!(render("{mtime}", &input), "");
+    }
+
+    #[test]
+    fn renders_file_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("four.txt");
+        std::fs::write(&path, "four").unwrap();
+        let name = path.to_string_lossy().into_owned();
+        let input = HashInput::File(&name);
+        assert_eq!(render("{type} {size} {basename}", &input), "file 4 four.txt");
+        assert!(render("{mtime}", &input).parse::<u64>().unwrap() > 0);
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for template in ["{nope}", "{hash", "}", "\\x", "trailing\\"] {
+            assert!(Template::parse(template).is_err(), "{}", template);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! TLSH locality sensitive hashes: the 128 bucket, 1 byte checksum variant printed by
+//! Trend Micro's `tlsh` tool as `T1` and 70 hex digits.
+//!
+//! Every 5 byte window adds six byte triplets, mapped through a Pearson hash, to 128
+//! buckets. Each bucket then becomes two bits saying which quartile of the bucket
+//! counts it falls in. The header holds a checksum, the log of the input length and the
+//! ratios of the quartiles. The distance between two digests is 0 for identical inputs
+//! and grows, without an upper bound, as they drift apart.
+
+const BUCKETS: usize = 128;
+const CODE_SIZE: usize = BUCKETS / 4;
+const WINDOW: usize = 5;
+const MIN_DATA_LENGTH: u64 = 50;
+
+/// Pearson permutation of the reference implementation.
+#[rustfmt::skip]
+const V_TABLE: [u8; 256] = [
+    1, 87, 49, 12, 176, 178, 102, 166, 121, 193, 6, 84, 249, 230, 44, 163,
+    14, 197, 213, 181, 161, 85, 218, 80, 64, 239, 24, 226, 236, 142, 38, 200,
+    110, 177, 104, 103, 141, 253, 255, 50, 77, 101, 81, 18, 45, 96, 31, 222,
+    25, 107, 190, 70, 86, 237, 240, 34, 72, 242, 20, 214, 244, 227, 149, 235,
+    97, 234, 57, 22, 60, 250, 82, 175, 208, 5, 127, 199, 111, 62, 135, 248,
+    174, 169, 211, 58, 66, 154, 106, 195, 245, 171, 17, 187, 182, 179, 0, 243,
+    132, 56, 148, 75, 128, 133, 158, 100, 130, 126, 91, 13, 153, 246, 216, 219,
+    119, 68, 223, 78, 83, 88, 201, 99, 122, 11, 92, 32, 136, 114, 52, 10,
+    138, 30, 48, 183, 156, 35, 61, 26, 143, 74, 251, 94, 129, 162, 63, 152,
+    170, 7, 115, 167, 241, 206, 3, 150, 55, 59, 151, 220, 90, 53, 23, 131,
+    125, 173, 15, 238, 79, 95, 89, 16, 105, 137, 225, 224, 217, 160, 37, 123,
+    118, 73, 2, 157, 46, 116, 9, 145, 134, 228, 207, 212, 202, 215, 69, 229,
+    27, 188, 67, 124, 168, 252, 42, 4, 29, 108, 21, 247, 19, 205, 39, 203,
+    233, 40, 186, 147, 198, 192, 155, 33, 164, 191, 98, 204, 165, 180, 117, 76,
+    140, 36, 210, 172, 41, 54, 159, 8, 185, 232, 113, 196, 231, 47, 146, 120,
+    51, 65, 28, 144, 254, 221, 93, 189, 194, 139, 112, 43, 71, 109, 184, 209,
+];
+
+fn b_mapping(salt: u8, i: u8, j: u8, k: u8) -> u8 {
+    let h = V_TABLE[salt as usize];
+    let h = V_TABLE[(h ^ i) as usize];
+    let h = V_TABLE[(h ^ j) as usize];
+    V_TABLE[(h ^ k) as usize]
+}
+
+fn swap_nibbles(b: u8) -> u8 {
+    b.rotate_left(4)
+}
+
+/// Logarithmic bucket of the input length.
+fn l_capturing(len: u64) -> u8 {
+    let len = len as f64;
+    let i = if len <= 656.0 {
+        (len.ln() / 0.405_465_1).floor()
+    } else if len <= 3199.0 {
+        (len.ln() / 0.262_364_26 - 8.727_77).floor()
+    } else {
+        (len.ln() / 0.095_310_18 - 62.5472).floor()
+    };
+    (i as i64 & 0xff) as u8
+}
+
+#[derive(Clone, Debug)]
+pub struct Tlsh {
+    buckets: [u32; BUCKETS],
+    window: [u8; WINDOW],
+    checksum: u8,
+    len: u64,
+}
+
+impl Default for Tlsh {
+    fn default() -> Self {
+        Tlsh { buckets: [0; BUCKETS], window: [0; WINDOW], checksum: 0, len: 0 }
+    }
+}
+
+impl Tlsh {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&mut self, triplet: u8) {
+        // Only the first half of the 256 possible buckets is used.
+        if let Some(count) = self.buckets.get_mut(triplet as usize) {
+            *count += 1;
+        }
+    }
+
+    pub fn update(&mut self, input: &[u8]) {
+        for &byte in input {
+            let j = (self.len % WINDOW as u64) as usize;
+            self.window[j] = byte;
+            self.len += 1;
+            if self.len < WINDOW as u64 {
+                continue;
+            }
+            let back = |n: usize| self.window[(j + WINDOW - n) % WINDOW];
+            let (w0, w1, w2, w3, w4) = (byte, back(1), back(2), back(3), back(4));
+            self.checksum = b_mapping(0, w0, w1, self.checksum);
+            self.add(b_mapping(2, w0, w1, w2));
+            self.add(b_mapping(3, w0, w1, w3));
+            self.add(b_mapping(5, w0, w2, w3));
+            self.add(b_mapping(7, w0, w2, w4));
+            self.add(b_mapping(11, w0, w1, w4));
+            self.add(b_mapping(13, w0, w3, w4));
+        }
+    }
+
+    pub fn digest(&self) -> Result<String, String> {
+        if self.len < MIN_DATA_LENGTH {
+            return Err(format!("TLSH needs at least {} bytes of input, got {}", MIN_DATA_LENGTH, self.len));
+        }
+        if self.len > u32::MAX as u64 {
+            return Err("Input is too large for a TLSH hash".to_string());
+        }
+        let mut sorted = self.buckets;
+        sorted.sort_unstable();
+        let (q1, q2, q3) = (sorted[BUCKETS / 4 - 1], sorted[BUCKETS / 2 - 1], sorted[BUCKETS - BUCKETS / 4 - 1]);
+        let nonzero = self.buckets.iter().filter(|&&c| c > 0).count();
+        if q3 == 0 || nonzero <= BUCKETS / 2 {
+            return Err("Input has too little variation for a TLSH hash".to_string());
+        }
+
+        let ratio = |q: u32| (q.wrapping_mul(100) as f32 / q3 as f32) as u32 % 16;
+        let mut bytes = vec![
+            swap_nibbles(self.checksum),
+            swap_nibbles(l_capturing(self.len)),
+            ((ratio(q2) << 4) | ratio(q1)) as u8,
+        ];
+        for chunk in self.buckets.chunks(4).rev() {
+            let mut code = 0u8;
+            for (n, &count) in chunk.iter().enumerate() {
+                let quartile = if count > q3 {
+                    3
+                } else if count > q2 {
+                    2
+                } else if count > q1 {
+                    1
+                } else {
+                    0
+                };
+                code |= quartile << (n * 2);
+            }
+            bytes.push(code);
+        }
+        Ok(format!("T1{}", crate::bytes_to_hex_string(&bytes).to_uppercase()))
+    }
+}
+
+struct Parsed {
+    checksum: u8,
+    lvalue: u8,
+    q1: u8,
+    q2: u8,
+    code: Vec<u8>,
+}
+
+fn parse(digest: &str) -> Option<Parsed> {
+    let digest = digest.trim();
+    let hex = digest.strip_prefix("T1").or_else(|| digest.strip_prefix("t1")).unwrap_or(digest);
+    let bytes = crate::encoding::decode_plain_hex(hex).filter(|b| b.len() == 3 + CODE_SIZE)?;
+    Some(Parsed {
+        checksum: swap_nibbles(bytes[0]),
+        lvalue: swap_nibbles(bytes[1]),
+        q1: bytes[2] & 0x0f,
+        q2: bytes[2] >> 4,
+        code: bytes[3..].to_vec(),
+    })
+}
+
+pub fn is_digest(digest: &str) -> bool {
+    parse(digest).is_some()
+}
+
+/// Distance between `x` and `y` on a circle of `range` values.
+fn mod_diff(x: u8, y: u8, range: u32) -> u32 {
+    let d = (x as i32 - y as i32).unsigned_abs();
+    d.min(range - d)
+}
+
+/// TLSH distance of two digests, including the length difference.
+pub fn distance(a: &str, b: &str) -> Result<u32, String> {
+    let a = parse(a).ok_or_else(|| format!("'{}' is not a TLSH digest", a))?;
+    let b = parse(b).ok_or_else(|| format!("'{}' is not a TLSH digest", b))?;
+    let mut diff = match mod_diff(a.lvalue, b.lvalue, 256) {
+        d @ 0..=1 => d,
+        d => d * 12,
+    };
+    for (x, y) in [(a.q1, b.q1), (a.q2, b.q2)] {
+        diff += match mod_diff(x, y, 16) {
+            d @ 0..=1 => d,
+            d => (d - 1) * 12,
+        };
+    }
+    if a.checksum != b.checksum {
+        diff += 1;
+    }
+    for (x, y) in a.code.iter().zip(b.code.iter()) {
+        for shift in (0..8).step_by(2) {
+            diff += match ((x >> shift) & 3).abs_diff((y >> shift) & 3) {
+                3 => 6,
+                d => d as u32,
+            };
+        }
+    }
+    Ok(diff)
+}
+
+/// Distances from this one on are treated as unrelated inputs. TLSH itself has no such
+/// cut-off; this one only gives `similarity` a 0 to 100 range.
+const UNRELATED_DISTANCE: u32 = 300;
+
+/// A 0 to 100 similarity from the TLSH distance: 100 for distance 0, falling linearly
+/// to 0 at a distance of 300. This is not a TLSH reference metric, the distance is.
+pub fn compare(a: &str, b: &str) -> Result<u32, String> {
+    let d = distance(a, b)?.min(UNRELATED_DISTANCE);
+    Ok((UNRELATED_DISTANCE - d) * 100 / UNRELATED_DISTANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOREM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua.";
+
+    fn fox(animal: &str) -> String {
+        "The quick brown fox jumps over the lazy dog. ".repeat(3) + &format!("The quick brown {} jumps over the lazy dog. ", animal)
+    }
+
+    fn digest(input: &[u8]) -> Result<String, String> {
+        let mut state = Tlsh::new();
+        state.update(input);
+        state.digest()
+    }
+
+    // No reference `tlsh` output is at hand to check digests against, so these tests
+    // only check properties every TLSH implementation has.
+    #[test]
+    fn split_updates_match_one_update() {
+        let input: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut state = Tlsh::new();
+        for chunk in input.chunks(97) {
+            state.update(chunk);
+        }
+        assert_eq!(state.digest(), digest(&input));
+    }
+
+    #[test]
+    fn distance_is_a_symmetric_measure() {
+        let a = digest(fox("fox").as_bytes()).unwrap();
+        let b = digest(fox("cat").as_bytes()).unwrap();
+        let c = digest(LOREM.as_bytes()).unwrap();
+        assert_eq!(distance(&a, &a), Ok(0));
+        assert_eq!(compare(&a, &a), Ok(100));
+        assert_eq!(distance(&a, &b), distance(&b, &a));
+        assert!(distance(&a, &b).unwrap() > 0);
+        assert!(distance(&a, &b).unwrap() < distance(&a, &c).unwrap());
+    }
+
+    #[test]
+    fn rejects_short_or_uniform_input() {
+        assert!(digest(&LOREM.as_bytes()[..49]).is_err());
+        assert!(digest(&[0u8; 4096]).is_err());
+    }
+
+    #[test]
+    fn parses_with_or_without_version() {
+        let a = digest(LOREM.as_bytes()).unwrap();
+        assert!(is_digest(&a));
+        assert!(is_digest(&a[2..].to_lowercase()));
+        assert!(!is_digest(&a[..40]));
+        assert_eq!(distance(&a, &a[2..]), Ok(0));
+    }
+}
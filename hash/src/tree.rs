@@ -0,0 +1,234 @@
+//! Merkle hash of a directory tree.
+//!
+//! Every node is hashed with the selected `HashAlgorithm`, written `H` below, and
+//! `||` is concatenation:
+//!
+//! * regular file: `H("file" || 0x00 || x || H(content))`, where `x` is `0x01` if any
+//!   executable bit is set and `0x00` otherwise;
+//! * symbolic link (never followed): `H("link" || 0x00 || target)`;
+//! * directory: `H("tree" || 0x00 || entry...)`, one entry per child sorted by the raw
+//!   bytes of its name, each encoded as `u64_be(len(name)) || name || H(child)`.
+//!
+//! Names and link targets are the raw OS bytes on Unix. The name of the root
+//! directory itself is not part of the encoding, so the same tree checked out at two
+//! different paths gives the same root. Other file types (sockets, FIFOs, devices) are
+//! skipped with a warning. Entries left out by `--exclude` or `--gitignore` are not
+//! part of the tree at all.
+
+use crate::cache::HashCache;
+use crate::select::FileFilter;
+use crate::{bytes_to_hex_string, HashAlgorithm, HashImpl};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+fn os_bytes(s: &OsStr) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        s.as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        s.to_string_lossy().as_bytes().to_vec()
+    }
+}
+
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+fn node_digest(tag: &[u8], body: &[u8], algo: HashAlgorithm) -> Vec<u8> {
+    let mut hasher = HashImpl::new();
+    hasher.update(tag);
+    hasher.update(&[0]);
+    hasher.update(body);
+    HashImpl::digest(&hasher.bytes, algo)
+}
+
+fn file_digest(
+    path: &Path,
+    metadata: &std::fs::Metadata,
+    algo: HashAlgorithm,
+    cache: &mut HashCache,
+) -> std::io::Result<Vec<u8>> {
+    let content = cache.file_digest(path, metadata, algo)?;
+    let mut body = vec![is_executable(metadata) as u8];
+    body.extend_from_slice(&content);
+    Ok(node_digest(b"file", &body, algo))
+}
+
+/// Compute the Merkle root of `dir`, leaving out what `filter` skips. When `subdirs`
+/// is given, the digest of every directory (including `dir` itself) is appended to it.
+pub fn tree_digest(
+    dir: &Path,
+    algo: HashAlgorithm,
+    filter: &FileFilter,
+    cache: &mut HashCache,
+    subdirs: &mut Option<Vec<(PathBuf, Vec<u8>)>>,
+) -> std::io::Result<Vec<u8>> {
+    let included: HashSet<PathBuf> = filter.walk_all(dir)?.into_iter().collect();
+    subtree_digest(dir, algo, &included, cache, subdirs)
+}
+
+fn subtree_digest(
+    dir: &Path,
+    algo: HashAlgorithm,
+    included: &HashSet<PathBuf>,
+    cache: &mut HashCache,
+    subdirs: &mut Option<Vec<(PathBuf, Vec<u8>)>>,
+) -> std::io::Result<Vec<u8>> {
+    let mut children: Vec<_> = std::fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|e| os_bytes(&e.file_name()));
+
+    let mut body = Vec::new();
+    for child in children {
+        let path = child.path();
+        if !included.contains(&path) {
+            continue;
+        }
+        let metadata = std::fs::symlink_metadata(&path)?;
+        let file_type = metadata.file_type();
+        let digest = if file_type.is_dir() {
+            subtree_digest(&path, algo, included, cache, subdirs)?
+        } else if file_type.is_file() {
+            file_digest(&path, &metadata, algo, cache)?
+        } else if file_type.is_symlink() {
+            node_digest(b"link", &os_bytes(std::fs::read_link(&path)?.as_os_str()), algo)
+        } else {
+            eprintln!("Skipping special file {}", path.display());
+            continue;
+        };
+        let name = os_bytes(&child.file_name());
+        body.extend_from_slice(&(name.len() as u64).to_be_bytes());
+        body.extend_from_slice(&name);
+        body.extend_from_slice(&digest);
+    }
+
+    let digest = node_digest(b"tree", &body, algo);
+    if let Some(list) = subdirs {
+        list.push((dir.to_path_buf(), digest.clone()));
+    }
+    Ok(digest)
+}
+
+/// Hash `dir` for the command line, exiting on I/O errors. The returned list holds
+/// every directory's digest when `list_subdirs` is set and is empty otherwise.
+pub fn compute_tree(
+    dir: &str,
+    algo: HashAlgorithm,
+    list_subdirs: bool,
+    filter: &FileFilter,
+    cache: &mut HashCache,
+) -> (String, Vec<(PathBuf, Vec<u8>)>) {
+    let mut subdirs = if list_subdirs { Some(Vec::new()) } else { None };
+    let root = match tree_digest(Path::new(dir), algo, filter, cache, &mut subdirs) {
+        Ok(d) => bytes_to_hex_string(&d),
+        Err(err) => {
+            eprintln!("Cannot hash tree {}: {}", dir, err);
+            std::process::exit(exitcode::IOERR);
+        }
+    };
+    (root, subdirs.unwrap_or_default())
+}
+
+pub fn print_subdirs(dir: &str, mut subdirs: Vec<(PathBuf, Vec<u8>)>) {
+    subdirs.sort();
+    for (path, digest) in subdirs {
+        let rel = path.strip_prefix(dir).unwrap_or(&path);
+        let rel = if rel.as_os_str().is_empty() { Path::new(".") } else { rel };
+        println!("{}  {}", bytes_to_hex_string(&digest), rel.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, Command};
+
+    fn tree_of(files: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in files {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+        dir
+    }
+
+    fn filter(args: &[&str]) -> FileFilter {
+        let matches = Command::new("hash")
+            .arg(Arg::new("exclude").long("exclude").takes_value(true).multiple_occurrences(true))
+            .arg(Arg::new("gitignore").long("gitignore"))
+            .get_matches_from(std::iter::once("hash").chain(args.iter().copied()));
+        FileFilter::from_matches(&matches)
+    }
+
+    fn root(dir: &Path) -> Vec<u8> {
+        filtered_root(dir, &FileFilter::default())
+    }
+
+    fn filtered_root(dir: &Path, filter: &FileFilter) -> Vec<u8> {
+        tree_digest(dir, HashAlgorithm::SHA256, filter, &mut HashCache::disabled(), &mut None).unwrap()
+    }
+
+    #[test]
+    fn known_roots() {
+        let empty = tempfile::tempdir().unwrap();
+        assert_eq!(bytes_to_hex_string(&root(empty.path())), "4c91b08843fdda8593f1552b5a6f10075310e7773fed804c20e24813c963f26f");
+        std::fs::write(empty.path().join("a"), "hello\n").unwrap();
+        assert_eq!(bytes_to_hex_string(&root(empty.path())), "5331bb38e5e8969c68c78bd404a1f3b28c38a44d878a01b4610842365c060da4");
+    }
+
+    #[test]
+    fn root_does_not_depend_on_the_location() {
+        let (a, b) = (tree_of(&["x", "sub/y"]), tree_of(&["x", "sub/y"]));
+        let (a, b) = (a.path(), b.path());
+        assert_eq!(root(a), root(b));
+        std::fs::write(b.join("sub/y"), "changed").unwrap();
+        assert_ne!(root(a), root(b));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn executable_bit_and_links_count() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tree_of(&["x"]);
+        let before = root(dir.path());
+        std::fs::set_permissions(dir.path().join("x"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        let executable = root(dir.path());
+        assert_ne!(before, executable);
+        std::os::unix::fs::symlink("x", dir.path().join("link")).unwrap();
+        assert_ne!(root(dir.path()), executable);
+    }
+
+    #[test]
+    fn lists_every_directory() {
+        let dir = tree_of(&["x", "sub/y", "sub/deeper/z"]);
+        let mut subdirs = Some(Vec::new());
+        let digest = tree_digest(dir.path(), HashAlgorithm::SHA256, &FileFilter::default(), &mut HashCache::disabled(), &mut subdirs).unwrap();
+        let subdirs = subdirs.unwrap();
+        let paths: Vec<_> = subdirs.iter().map(|(p, _)| p.strip_prefix(dir.path()).unwrap()).collect();
+        assert_eq!(paths, [Path::new("sub/deeper"), Path::new("sub"), Path::new("")]);
+        assert_eq!(subdirs[2].1, digest);
+    }
+
+    #[test]
+    fn excluded_entries_are_left_out() {
+        let (full, kept) = (tree_of(&["x", "build/out.o", "sub/y", "sub/z.o"]), tree_of(&["x", "sub/y"]));
+        let (full, kept) = (full.path(), kept.path());
+        assert_eq!(filtered_root(full, &filter(&["--exclude", "build", "--exclude", "*.o"])), root(kept));
+        std::fs::write(full.join(".gitignore"), "build\n*.o\n").unwrap();
+        std::fs::write(kept.join(".gitignore"), "build\n*.o\n").unwrap();
+        assert_eq!(filtered_root(full, &filter(&["--gitignore"])), root(kept));
+    }
+}
@@ -0,0 +1,203 @@
+use crate::cache::HashCache;
+use crate::select::FileFilter;
+use crate::{bytes_to_hex_string, read_file, tree, HashAlgorithm, HashImpl, HashInput, InputOptions, OutputStyle};
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+struct Watched<'a> {
+    input: &'a HashInput<'a>,
+    path: PathBuf,
+    last_hash: Option<String>,
+    dirty_since: Option<Instant>,
+}
+
+impl<'a> Watched<'a> {
+    fn matches(&self, event_path: &Path) -> bool {
+        match self.input {
+            HashInput::Tree(_) => event_path.starts_with(&self.path),
+            _ => event_path == self.path,
+        }
+    }
+
+    fn digest(&self, algo: HashAlgorithm, options: &InputOptions, filter: &FileFilter) -> std::io::Result<String> {
+        match self.input {
+            HashInput::File(file) => {
+                let bytes = read_file(file, options)?;
+                Ok(HashImpl::hex_digest_input(&bytes, algo))
+            }
+            HashInput::Tree(dir) => Ok(bytes_to_hex_string(&tree::tree_digest(
+                Path::new(dir),
+                algo,
+                filter,
+                &mut HashCache::disabled(),
+                &mut None,
+            )?)),
+            HashInput::Text(_) => unreachable!("text inputs are never watched"),
+        }
+    }
+
+    fn print(&self, algo: HashAlgorithm, print_hash_only: bool) {
+        let hash = self.last_hash.as_deref().unwrap_or_default();
+        if print_hash_only {
+            println!("{}", hash);
+            return;
+        }
+        let mut style = OutputStyle::new();
+        style.set_algorithm(algo);
+        match self.input {
+            HashInput::File(file) => style.add_file(file),
+            HashInput::Tree(dir) => style.add_tree(dir),
+            HashInput::Text(text) => style.add_text(text),
+        }
+        style.add_hash(hash);
+        println!("{}", style.summary("CHANGE"));
+    }
+}
+
+fn is_content_change(kind: &EventKind) -> bool {
+    match kind {
+        // Our own reads show up as open/close events and must not retrigger hashing.
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    }
+}
+
+/// Keep re-hashing the file and tree inputs whenever they change on disk, printing a
+/// record once an input has been quiet for `debounce` and its digest differs from the
+/// last one printed. Never returns unless the watcher fails.
+pub fn watch(
+    inputs: &[HashInput],
+    algo: HashAlgorithm,
+    options: &InputOptions,
+    filter: &FileFilter,
+    print_hash_only: bool,
+    debounce: Duration,
+) {
+    let (tx, rx) = channel::<notify::Result<Event>>();
+    let mut watcher = match notify::recommended_watcher(tx) {
+        Ok(w) => w,
+        Err(err) => {
+            eprintln!("Cannot start watcher: {}", err);
+            std::process::exit(exitcode::OSERR);
+        }
+    };
+
+    let mut watched = Vec::new();
+    for input in inputs.iter() {
+        let (name, mode) = match input {
+            HashInput::File(file) => (*file, RecursiveMode::NonRecursive),
+            HashInput::Tree(dir) => (*dir, RecursiveMode::Recursive),
+            HashInput::Text(_) => continue,
+        };
+        let path = match std::fs::canonicalize(name) {
+            Ok(p) => p,
+            Err(err) => {
+                eprintln!("Cannot watch {}: {}", name, err);
+                std::process::exit(exitcode::IOERR);
+            }
+        };
+        // Editors usually replace a file by renaming over it, which drops an inotify
+        // watch on the file itself, so single files are watched through their parent.
+        let target = match input {
+            HashInput::File(_) => path.parent().unwrap_or(&path).to_path_buf(),
+            _ => path.clone(),
+        };
+        if let Err(err) = watcher.watch(&target, mode) {
+            eprintln!("Cannot watch {}: {}", name, err);
+            std::process::exit(exitcode::IOERR);
+        }
+        let mut entry = Watched {
+            input,
+            path,
+            last_hash: None,
+            dirty_since: None,
+        };
+        entry.last_hash = entry.digest(algo, options, filter).ok();
+        watched.push(entry);
+    }
+
+    if watched.is_empty() {
+        eprintln!("Nothing to watch, --watch needs at least one --file or --tree input");
+        std::process::exit(exitcode::USAGE);
+    }
+
+    loop {
+        let timeout = watched
+            .iter()
+            .filter_map(|w| w.dirty_since)
+            .map(|t| (t + debounce).saturating_duration_since(Instant::now()))
+            .min()
+            .unwrap_or(Duration::from_secs(3600));
+
+        match rx.recv_timeout(timeout) {
+            Ok(Ok(event)) => {
+                if !is_content_change(&event.kind) {
+                    continue;
+                }
+                let now = Instant::now();
+                for w in watched.iter_mut() {
+                    if event.paths.iter().any(|p| w.matches(p)) {
+                        w.dirty_since = Some(now);
+                    }
+                }
+            }
+            Ok(Err(err)) => eprintln!("Watch error: {}", err),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        for w in watched.iter_mut() {
+            match w.dirty_since {
+                Some(t) if now.duration_since(t) >= debounce => w.dirty_since = None,
+                _ => continue,
+            }
+            match w.digest(algo, options, filter) {
+                Ok(hash) => {
+                    if w.last_hash.as_ref() != Some(&hash) {
+                        w.last_hash = Some(hash);
+                        w.print(algo, print_hash_only);
+                    }
+                }
+                Err(err) => {
+                    if w.last_hash.take().is_some() {
+                        eprintln!("Cannot read {}: {}", w.path.display(), err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, ModifyKind};
+
+    fn watched<'a>(input: &'a HashInput<'a>, path: &str) -> Watched<'a> {
+        Watched { input, path: PathBuf::from(path), last_hash: None, dirty_since: None }
+    }
+
+    #[test]
+    fn matches_events_under_the_input() {
+        let file = HashInput::File("dir/a.txt");
+        let tree = HashInput::Tree("dir");
+        assert!(watched(&file, "/w/dir/a.txt").matches(Path::new("/w/dir/a.txt")));
+        assert!(!watched(&file, "/w/dir/a.txt").matches(Path::new("/w/dir/b.txt")));
+        assert!(watched(&tree, "/w/dir").matches(Path::new("/w/dir/sub/b.txt")));
+        assert!(!watched(&tree, "/w/dir").matches(Path::new("/w/dir2/b.txt")));
+    }
+
+    #[test]
+    fn ignores_reads() {
+        assert!(is_content_change(&EventKind::Modify(ModifyKind::Any)));
+        assert!(is_content_change(&EventKind::Create(CreateKind::File)));
+        assert!(is_content_change(&EventKind::Access(AccessKind::Close(AccessMode::Write))));
+        assert!(!is_content_change(&EventKind::Access(AccessKind::Close(AccessMode::Read))));
+        assert!(!is_content_change(&EventKind::Access(AccessKind::Open(AccessMode::Any))));
+    }
+}